@@ -0,0 +1,4 @@
+USE gitnofity;
+
+ALTER TABLE subscriptions
+    ADD COLUMN notify_on_force_push BOOLEAN NOT NULL DEFAULT TRUE AFTER notify_on_pr_update;
@@ -0,0 +1,6 @@
+USE gitnofity;
+
+ALTER TABLE subscriptions
+    ADD COLUMN notify_on_branch_delete BOOLEAN NOT NULL DEFAULT TRUE AFTER notify_on_force_push,
+    ADD COLUMN notify_on_tag_delete BOOLEAN NOT NULL DEFAULT TRUE AFTER notify_on_branch_delete,
+    ADD COLUMN notify_on_pr_close BOOLEAN NOT NULL DEFAULT TRUE AFTER notify_on_tag_delete;
@@ -0,0 +1,12 @@
+USE gitnofity;
+
+CREATE TABLE IF NOT EXISTS subscription_filters (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    repository_id INT NOT NULL,
+    ref_kind VARCHAR(16) NOT NULL,
+    mode VARCHAR(16) NOT NULL,
+    pattern VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id, repository_id) REFERENCES subscriptions(user_id, repository_id) ON DELETE CASCADE
+);
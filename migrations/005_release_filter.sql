@@ -0,0 +1,4 @@
+USE gitnofity;
+
+ALTER TABLE subscriptions
+    ADD COLUMN release_filter VARCHAR(16) NOT NULL DEFAULT 'all' AFTER notify_on_pr_close;
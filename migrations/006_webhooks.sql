@@ -0,0 +1,5 @@
+USE gitnofity;
+
+ALTER TABLE repositories
+    ADD COLUMN webhook_secret VARCHAR(64) AFTER url_hash,
+    ADD COLUMN webhook_active BOOLEAN NOT NULL DEFAULT FALSE AFTER webhook_secret;
@@ -0,0 +1,8 @@
+USE gitnofity;
+
+ALTER TABLE repositories
+    ADD COLUMN next_check_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP AFTER webhook_active,
+    ADD COLUMN consecutive_failures INT NOT NULL DEFAULT 0 AFTER next_check_at,
+    ADD COLUMN last_change_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP AFTER consecutive_failures;
+
+CREATE INDEX idx_repositories_next_check_at ON repositories (next_check_at);
@@ -0,0 +1,5 @@
+USE gitnofity;
+
+ALTER TABLE repositories
+    ADD COLUMN first_failure_at TIMESTAMP NULL AFTER consecutive_failures,
+    ADD COLUMN unreachable_notified BOOLEAN NOT NULL DEFAULT FALSE AFTER first_failure_at;
@@ -0,0 +1,13 @@
+USE gitnofity;
+
+CREATE TABLE IF NOT EXISTS notification_outbox (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    message TEXT NOT NULL,
+    reply_markup TEXT,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX (next_attempt_at),
+    FOREIGN KEY (chat_id) REFERENCES users(id) ON DELETE CASCADE
+);
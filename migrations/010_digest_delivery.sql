@@ -0,0 +1,20 @@
+USE gitnofity;
+
+ALTER TABLE users
+    ADD COLUMN delivery_mode VARCHAR(16) NOT NULL DEFAULT 'immediate' AFTER notifications_enabled,
+    ADD COLUMN digest_time TIME NOT NULL DEFAULT '09:00:00' AFTER delivery_mode,
+    ADD COLUMN timezone VARCHAR(64) NOT NULL DEFAULT 'UTC' AFTER digest_time;
+
+ALTER TABLE subscriptions
+    ADD COLUMN delivery_mode VARCHAR(16) AFTER release_filter;
+
+CREATE TABLE IF NOT EXISTS digest_entries (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    repo_url VARCHAR(2048) NOT NULL,
+    event TEXT NOT NULL,
+    delivery_mode VARCHAR(16) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX (chat_id, delivery_mode),
+    FOREIGN KEY (chat_id) REFERENCES users(id) ON DELETE CASCADE
+);
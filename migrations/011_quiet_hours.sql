@@ -0,0 +1,9 @@
+USE gitnofity;
+
+ALTER TABLE users
+    ADD COLUMN quiet_hours_start TIME AFTER timezone,
+    ADD COLUMN quiet_hours_end TIME AFTER quiet_hours_start,
+    ADD COLUMN quiet_hours_mode VARCHAR(16) NOT NULL DEFAULT 'hold' AFTER quiet_hours_end;
+
+ALTER TABLE notification_outbox
+    ADD COLUMN disable_notification BOOLEAN NOT NULL DEFAULT FALSE AFTER reply_markup;
@@ -0,0 +1,4 @@
+USE gitnofity;
+
+ALTER TABLE subscriptions
+    ADD COLUMN muted_until TIMESTAMP NULL AFTER delivery_mode;
@@ -0,0 +1,12 @@
+USE gitnofity;
+
+CREATE TABLE IF NOT EXISTS pending_branch_updates (
+    repository_id INT NOT NULL,
+    ref_name VARCHAR(255) NOT NULL,
+    old_sha VARCHAR(64) NOT NULL,
+    new_sha VARCHAR(64) NOT NULL,
+    first_seen_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (repository_id, ref_name),
+    FOREIGN KEY (repository_id) REFERENCES repositories(id) ON DELETE CASCADE
+);
@@ -0,0 +1,16 @@
+USE gitnofity;
+
+RENAME TABLE users TO chats;
+
+ALTER TABLE chats
+    ADD COLUMN kind VARCHAR(16) NOT NULL DEFAULT 'private' AFTER id,
+    ADD COLUMN title VARCHAR(255) AFTER kind;
+
+ALTER TABLE subscription_filters DROP FOREIGN KEY subscription_filters_ibfk_1;
+
+ALTER TABLE subscriptions RENAME COLUMN user_id TO chat_id;
+
+ALTER TABLE subscription_filters
+    RENAME COLUMN user_id TO chat_id,
+    ADD FOREIGN KEY (chat_id, repository_id) REFERENCES subscriptions(chat_id, repository_id)
+        ON DELETE CASCADE ON UPDATE CASCADE;
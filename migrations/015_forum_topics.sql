@@ -0,0 +1,13 @@
+USE gitnofity;
+
+ALTER TABLE subscriptions
+    ADD COLUMN message_thread_id INT AFTER muted_until,
+    ADD COLUMN branch_thread_id INT AFTER message_thread_id,
+    ADD COLUMN tag_thread_id INT AFTER branch_thread_id,
+    ADD COLUMN pr_thread_id INT AFTER tag_thread_id;
+
+ALTER TABLE notification_outbox
+    ADD COLUMN message_thread_id INT AFTER chat_id;
+
+ALTER TABLE digest_entries
+    ADD COLUMN message_thread_id INT AFTER chat_id;
@@ -0,0 +1,7 @@
+USE gitnofity;
+
+ALTER TABLE repositories
+    ADD COLUMN requires_credentials BOOLEAN NOT NULL DEFAULT FALSE AFTER url_hash;
+
+ALTER TABLE subscriptions
+    ADD COLUMN credentials BLOB AFTER pr_thread_id;
@@ -0,0 +1,4 @@
+USE gitnofity;
+
+ALTER TABLE subscriptions
+    ADD COLUMN include_tag_changelog BOOLEAN NOT NULL DEFAULT TRUE AFTER notify_on_pr_close;
@@ -0,0 +1,4 @@
+USE gitnofity;
+
+-- Peeled "^{}" entries are no longer listed; drop the ones stored as tags of their own.
+DELETE FROM repository_refs WHERE ref_name LIKE 'refs/tags/%^{}';
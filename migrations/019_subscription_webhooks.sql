@@ -0,0 +1,9 @@
+USE gitnofity;
+
+ALTER TABLE repositories
+    DROP COLUMN webhook_secret,
+    DROP COLUMN webhook_active;
+
+ALTER TABLE subscriptions
+    ADD COLUMN webhook_secret VARCHAR(64) AFTER credentials,
+    ADD COLUMN webhook_delivered_at TIMESTAMP NULL AFTER webhook_secret;
@@ -0,0 +1,10 @@
+USE gitnofity;
+
+ALTER TABLE repositories
+    ADD COLUMN credentials_checked_at TIMESTAMP NULL AFTER requires_credentials;
+
+ALTER TABLE subscriptions
+    ADD COLUMN credentials_valid BOOLEAN NOT NULL DEFAULT FALSE AFTER credentials;
+
+-- Stored credentials were checked when they were added, the next poll verifies them again.
+UPDATE subscriptions SET credentials_valid = TRUE WHERE credentials IS NOT NULL;
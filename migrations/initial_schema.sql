@@ -1,10 +1,18 @@
 CREATE DATABASE IF NOT EXISTS gitnofity;
 USE gitnofity;
 
-CREATE TABLE IF NOT EXISTS users (
+CREATE TABLE IF NOT EXISTS chats (
     id BIGINT PRIMARY KEY,
+    kind VARCHAR(16) NOT NULL DEFAULT 'private',
+    title VARCHAR(255),
     username VARCHAR(255),
     notifications_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    delivery_mode VARCHAR(16) NOT NULL DEFAULT 'immediate',
+    digest_time TIME NOT NULL DEFAULT '09:00:00',
+    timezone VARCHAR(64) NOT NULL DEFAULT 'UTC',
+    quiet_hours_start TIME,
+    quiet_hours_end TIME,
+    quiet_hours_mode VARCHAR(16) NOT NULL DEFAULT 'hold',
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
@@ -12,20 +20,44 @@ CREATE TABLE IF NOT EXISTS repositories (
     id INT AUTO_INCREMENT PRIMARY KEY,
     url VARCHAR(2048) NOT NULL,
     url_hash VARCHAR(64) NOT NULL UNIQUE,
-    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+    requires_credentials BOOLEAN NOT NULL DEFAULT FALSE,
+    credentials_checked_at TIMESTAMP NULL,
+    next_check_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    consecutive_failures INT NOT NULL DEFAULT 0,
+    first_failure_at TIMESTAMP NULL,
+    unreachable_notified BOOLEAN NOT NULL DEFAULT FALSE,
+    last_change_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX (next_check_at)
 );
 
 CREATE TABLE IF NOT EXISTS subscriptions (
-    user_id BIGINT NOT NULL,
+    chat_id BIGINT NOT NULL,
     repository_id INT NOT NULL,
     notify_on_new_branch BOOLEAN NOT NULL DEFAULT TRUE,
     notify_on_new_tag BOOLEAN NOT NULL DEFAULT TRUE,
     notify_on_branch_update BOOLEAN NOT NULL DEFAULT TRUE,
     notify_on_new_pr BOOLEAN NOT NULL DEFAULT TRUE,
     notify_on_pr_update BOOLEAN NOT NULL DEFAULT TRUE,
+    notify_on_force_push BOOLEAN NOT NULL DEFAULT TRUE,
+    notify_on_branch_delete BOOLEAN NOT NULL DEFAULT TRUE,
+    notify_on_tag_delete BOOLEAN NOT NULL DEFAULT TRUE,
+    notify_on_pr_close BOOLEAN NOT NULL DEFAULT TRUE,
+    include_tag_changelog BOOLEAN NOT NULL DEFAULT TRUE,
+    release_filter VARCHAR(16) NOT NULL DEFAULT 'all',
+    delivery_mode VARCHAR(16),
+    muted_until TIMESTAMP NULL,
+    message_thread_id INT,
+    branch_thread_id INT,
+    tag_thread_id INT,
+    pr_thread_id INT,
+    credentials BLOB,
+    credentials_valid BOOLEAN NOT NULL DEFAULT FALSE,
+    webhook_secret VARCHAR(64),
+    webhook_delivered_at TIMESTAMP NULL,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
-    PRIMARY KEY (user_id, repository_id),
-    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (chat_id, repository_id),
+    FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE,
     FOREIGN KEY (repository_id) REFERENCES repositories(id) ON DELETE CASCADE
 );
 
@@ -38,3 +70,51 @@ CREATE TABLE IF NOT EXISTS repository_refs (
     UNIQUE KEY (repository_id, ref_name),
     FOREIGN KEY (repository_id) REFERENCES repositories(id) ON DELETE CASCADE
 );
+
+CREATE TABLE IF NOT EXISTS subscription_filters (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    repository_id INT NOT NULL,
+    ref_kind VARCHAR(16) NOT NULL,
+    mode VARCHAR(16) NOT NULL,
+    pattern VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (chat_id, repository_id) REFERENCES subscriptions(chat_id, repository_id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS notification_outbox (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    message_thread_id INT,
+    message TEXT NOT NULL,
+    reply_markup TEXT,
+    disable_notification BOOLEAN NOT NULL DEFAULT FALSE,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX (next_attempt_at),
+    FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS digest_entries (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    message_thread_id INT,
+    repo_url VARCHAR(2048) NOT NULL,
+    event TEXT NOT NULL,
+    delivery_mode VARCHAR(16) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX (chat_id, delivery_mode),
+    FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS pending_branch_updates (
+    repository_id INT NOT NULL,
+    ref_name VARCHAR(255) NOT NULL,
+    old_sha VARCHAR(64) NOT NULL,
+    new_sha VARCHAR(64) NOT NULL,
+    first_seen_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (repository_id, ref_name),
+    FOREIGN KEY (repository_id) REFERENCES repositories(id) ON DELETE CASCADE
+);
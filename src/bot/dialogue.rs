@@ -1,3 +1,4 @@
+use crate::core::filters::{FilterMode, RefKind};
 use serde::{Deserialize, Serialize};
 pub use teloxide::dispatching::dialogue::InMemStorage;
 
@@ -6,6 +7,19 @@ pub enum State {
     #[default]
     Start,
     ReceiveRepoUrl,
+    ReceiveFilterPattern {
+        repo_id: i32,
+        ref_kind: RefKind,
+        mode: FilterMode,
+    },
+    ReceiveDigestTime,
+    ReceiveTimezone,
+    ReceiveQuietHours,
+    ReceiveImportFile,
+    ReceivePrivateRepoUrl,
+    ReceiveCredentials {
+        url: String,
+    },
 }
 
 pub type Dialogue = teloxide::dispatching::dialogue::Dialogue<State, InMemStorage<State>>;
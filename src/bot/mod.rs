@@ -1,2 +1,8 @@
 pub mod dialogue;
+pub mod permissions;
 pub mod ui;
+
+use teloxide::adaptors::Throttle;
+use teloxide::Bot;
+
+pub type AppBot = Throttle<Bot>;
@@ -0,0 +1,34 @@
+use crate::bot::AppBot;
+use teloxide::prelude::*;
+use teloxide::types::{Chat, User};
+use teloxide::RequestError;
+
+// Only administrators may change what a group or channel is subscribed to.
+pub async fn can_manage_subscriptions(
+    bot: &AppBot,
+    chat: &Chat,
+    user: Option<&User>,
+    sender_chat: Option<&Chat>,
+) -> Result<bool, RequestError> {
+    if chat.is_private() {
+        return Ok(true);
+    }
+
+    // Messages sent on behalf of a chat come from a placeholder user. Anonymous admins
+    // speak as the group itself and only admins of its linked channel can post as that,
+    // any other channel a member picks proves nothing.
+    if let Some(sender_chat) = sender_chat {
+        if sender_chat.id == chat.id {
+            return Ok(true);
+        }
+        let linked_chat_id = bot.get_chat(chat.id).await?.linked_chat_id();
+        return Ok(linked_chat_id == Some(sender_chat.id.0));
+    }
+
+    match user {
+        // Channel posts carry no sender and can only be written by channel admins.
+        None => Ok(chat.is_channel()),
+        Some(user) if user.is_anonymous() => Ok(true),
+        Some(user) => Ok(bot.get_chat_member(chat.id, user.id).await?.kind.is_privileged()),
+    }
+}
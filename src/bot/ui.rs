@@ -1,4 +1,7 @@
-use crate::infrastructure::db::{Repository, SubscriptionSettings};
+use crate::core::digest::DeliveryMode;
+use crate::core::filters::RefFilter;
+use crate::core::topics::EventCategory;
+use crate::infrastructure::db::{DeliveryPreferences, Repository, SubscriptionSettings};
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 
 pub fn subscriptions_menu(subscriptions: &[Repository]) -> InlineKeyboardMarkup {
@@ -25,6 +28,22 @@ pub fn repository_menu(repo_id: i32) -> InlineKeyboardMarkup {
         "⚙️ Notification Settings",
         format!("repo_settings_{}", repo_id),
     )]);
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        "🔍 Branch & Tag Filters",
+        format!("repo_filters_{}", repo_id),
+    )]);
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        "🪝 Webhook",
+        format!("repo_webhook_{}", repo_id),
+    )]);
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        "🔕 Snooze",
+        format!("repo_snooze_{}", repo_id),
+    )]);
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        "🧵 Topic Routing",
+        format!("repo_topics_{}", repo_id),
+    )]);
     keyboard.push(vec![InlineKeyboardButton::callback(
         "❌ Unsubscribe",
         format!("unsubscribe_{}", repo_id),
@@ -62,6 +81,21 @@ pub fn notification_settings_menu(
         format!("toggle_setting_{}_new_tag", repo_id),
     )]);
 
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        format!("🏷️ Releases: {}", settings.release_filter.label()),
+        format!("toggle_setting_{}_release_filter", repo_id),
+    )]);
+
+    let tag_changelog_text = if settings.include_tag_changelog {
+        "✅ Commit Log in Releases"
+    } else {
+        "❌ Commit Log in Releases"
+    };
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        tag_changelog_text,
+        format!("toggle_setting_{}_tag_changelog", repo_id),
+    )]);
+
     let branch_update_text = if settings.notify_on_branch_update {
         "✅ Branch Updated"
     } else {
@@ -72,6 +106,16 @@ pub fn notification_settings_menu(
         format!("toggle_setting_{}_branch_update", repo_id),
     )]);
 
+    let force_push_text = if settings.notify_on_force_push {
+        "✅ Branch Force-Pushed"
+    } else {
+        "❌ Branch Force-Pushed"
+    };
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        force_push_text,
+        format!("toggle_setting_{}_force_push", repo_id),
+    )]);
+
     let new_pr_text = if settings.notify_on_new_pr {
         "✅ New Pull Request"
     } else {
@@ -92,6 +136,148 @@ pub fn notification_settings_menu(
         format!("toggle_setting_{}_pr_update", repo_id),
     )]);
 
+    let branch_delete_text = if settings.notify_on_branch_delete {
+        "✅ Branch Deleted"
+    } else {
+        "❌ Branch Deleted"
+    };
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        branch_delete_text,
+        format!("toggle_setting_{}_branch_delete", repo_id),
+    )]);
+
+    let tag_delete_text = if settings.notify_on_tag_delete {
+        "✅ Tag Deleted"
+    } else {
+        "❌ Tag Deleted"
+    };
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        tag_delete_text,
+        format!("toggle_setting_{}_tag_delete", repo_id),
+    )]);
+
+    let pr_close_text = if settings.notify_on_pr_close {
+        "✅ Pull Request Closed"
+    } else {
+        "❌ Pull Request Closed"
+    };
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        pr_close_text,
+        format!("toggle_setting_{}_pr_close", repo_id),
+    )]);
+
+    let delivery_label = settings
+        .delivery_mode
+        .map_or("Default", |mode| mode.label());
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        format!("📬 Delivery: {}", delivery_label),
+        format!("toggle_setting_{}_delivery_mode", repo_id),
+    )]);
+
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        "⬅️ Back to Repository",
+        format!("view_repo_{}", repo_id),
+    )]);
+
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+pub fn filters_menu(repo_id: i32, filters: &[RefFilter]) -> InlineKeyboardMarkup {
+    let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
+
+    for filter in filters {
+        keyboard.push(vec![InlineKeyboardButton::callback(
+            format!(
+                "🗑 {} {}: {}",
+                filter.mode.as_str(),
+                filter.ref_kind.as_str(),
+                filter.pattern
+            ),
+            format!("remove_filter_{}_{}", repo_id, filter.id),
+        )]);
+    }
+
+    keyboard.push(vec![
+        InlineKeyboardButton::callback(
+            "➕ Include branches",
+            format!("add_filter_{}_branch_include", repo_id),
+        ),
+        InlineKeyboardButton::callback(
+            "➖ Exclude branches",
+            format!("add_filter_{}_branch_exclude", repo_id),
+        ),
+    ]);
+    keyboard.push(vec![
+        InlineKeyboardButton::callback(
+            "➕ Include tags",
+            format!("add_filter_{}_tag_include", repo_id),
+        ),
+        InlineKeyboardButton::callback(
+            "➖ Exclude tags",
+            format!("add_filter_{}_tag_exclude", repo_id),
+        ),
+    ]);
+
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        "⬅️ Back to Repository",
+        format!("view_repo_{}", repo_id),
+    )]);
+
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+pub fn webhook_menu(repo_id: i32) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![
+        vec![InlineKeyboardButton::callback(
+            "🔄 Regenerate Secret",
+            format!("webhook_regen_{}", repo_id),
+        )],
+        vec![InlineKeyboardButton::callback(
+            "⬅️ Back to Repository",
+            format!("view_repo_{}", repo_id),
+        )],
+    ])
+}
+
+pub fn snooze_menu(repo_id: i32, is_snoozed: bool) -> InlineKeyboardMarkup {
+    let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![vec![
+        InlineKeyboardButton::callback("8 hours", format!("snooze_{}_8h", repo_id)),
+        InlineKeyboardButton::callback("1 day", format!("snooze_{}_1d", repo_id)),
+        InlineKeyboardButton::callback("1 week", format!("snooze_{}_1w", repo_id)),
+    ]];
+
+    if is_snoozed {
+        keyboard.push(vec![InlineKeyboardButton::callback(
+            "🔔 Unsnooze",
+            format!("snooze_{}_off", repo_id),
+        )]);
+    }
+
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        "⬅️ Back to Repository",
+        format!("view_repo_{}", repo_id),
+    )]);
+
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+pub fn topic_routing_menu(repo_id: i32) -> InlineKeyboardMarkup {
+    let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![vec![InlineKeyboardButton::callback(
+        "📌 All events → this topic",
+        format!("route_here_{}_all", repo_id),
+    )]];
+
+    for category in [EventCategory::Branch, EventCategory::Tag, EventCategory::PullRequest] {
+        keyboard.push(vec![InlineKeyboardButton::callback(
+            format!("📌 {} → this topic", category.label()),
+            format!("route_here_{}_{}", repo_id, category.as_str()),
+        )]);
+    }
+
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        "♻️ Reset Routing",
+        format!("route_reset_{}", repo_id),
+    )]);
     keyboard.push(vec![InlineKeyboardButton::callback(
         "⬅️ Back to Repository",
         format!("view_repo_{}", repo_id),
@@ -100,6 +286,66 @@ pub fn notification_settings_menu(
     InlineKeyboardMarkup::new(keyboard)
 }
 
+pub fn unreachable_repository_menu(repo_id: i32) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("🔁 Keep trying for everyone", format!("keep_trying_{}", repo_id)),
+        InlineKeyboardButton::callback("❌ Unsubscribe", format!("unsubscribe_{}", repo_id)),
+    ]])
+}
+
+pub fn delivery_settings_menu(preferences: &DeliveryPreferences) -> InlineKeyboardMarkup {
+    let mut keyboard: Vec<Vec<InlineKeyboardButton>> = vec![];
+
+    for mode in [DeliveryMode::Immediate, DeliveryMode::Hourly, DeliveryMode::Daily] {
+        let marker = if mode == preferences.delivery_mode {
+            "✅"
+        } else {
+            "▫️"
+        };
+        keyboard.push(vec![InlineKeyboardButton::callback(
+            format!("{} {}", marker, mode.label()),
+            format!("set_delivery_{}", mode.as_str()),
+        )]);
+    }
+
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        format!("🕘 Digest time: {}", preferences.digest_time.format("%H:%M")),
+        "set_digest_time",
+    )]);
+    keyboard.push(vec![InlineKeyboardButton::callback(
+        format!("🌍 Timezone: {}", preferences.timezone),
+        "set_timezone",
+    )]);
+
+    InlineKeyboardMarkup::new(keyboard)
+}
+
+pub fn quiet_hours_menu(preferences: &DeliveryPreferences) -> InlineKeyboardMarkup {
+    let window = match &preferences.quiet_hours {
+        Some(quiet_hours) => format!(
+            "{}–{}",
+            quiet_hours.start.format("%H:%M"),
+            quiet_hours.end.format("%H:%M")
+        ),
+        None => "Off".to_string(),
+    };
+
+    InlineKeyboardMarkup::new(vec![
+        vec![InlineKeyboardButton::callback(
+            format!("🌙 Quiet hours: {}", window),
+            "set_quiet_hours",
+        )],
+        vec![InlineKeyboardButton::callback(
+            format!("🔔 During quiet hours: {}", preferences.quiet_hours_mode.label()),
+            "toggle_quiet_mode",
+        )],
+        vec![InlineKeyboardButton::callback(
+            format!("🌍 Timezone: {}", preferences.timezone),
+            "set_timezone",
+        )],
+    ])
+}
+
 pub fn global_notification_toggle_menu(is_enabled: bool) -> InlineKeyboardMarkup {
     let toggle_text = if is_enabled {
         "✅ All Notifications ON"
@@ -0,0 +1,212 @@
+use crate::core::digest::DeliveryMode;
+use crate::core::filters::{self, FilterMode, RefKind};
+use crate::core::git_service;
+use crate::core::releases::ReleaseFilter;
+use crate::core::repo_url;
+use crate::infrastructure::db::{self, DbError, DbPool, SubscriptionSettings};
+use serde::{Deserialize, Serialize};
+use teloxide::types::Chat;
+
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubscriptionExport {
+    pub version: u32,
+    pub repositories: Vec<ExportedRepository>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedRepository {
+    pub url: String,
+    #[serde(default)]
+    pub settings: Option<ExportedSettings>,
+    #[serde(default)]
+    pub filters: Vec<ExportedFilter>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedSettings {
+    pub new_branch: bool,
+    pub new_tag: bool,
+    pub branch_update: bool,
+    pub force_push: bool,
+    pub new_pr: bool,
+    pub pr_update: bool,
+    pub branch_delete: bool,
+    pub tag_delete: bool,
+    pub pr_close: bool,
+    #[serde(default = "default_tag_changelog")]
+    pub tag_changelog: bool,
+    pub release_filter: String,
+    pub delivery_mode: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedFilter {
+    pub ref_kind: String,
+    pub mode: String,
+    pub pattern: String,
+}
+
+pub enum ImportOutcome {
+    Imported,
+    Invalid(String),
+    Unreachable,
+    Failed,
+}
+
+// Exports made before the option existed should keep the new default.
+fn default_tag_changelog() -> bool {
+    true
+}
+
+impl From<&SubscriptionSettings> for ExportedSettings {
+    fn from(settings: &SubscriptionSettings) -> Self {
+        ExportedSettings {
+            new_branch: settings.notify_on_new_branch,
+            new_tag: settings.notify_on_new_tag,
+            branch_update: settings.notify_on_branch_update,
+            force_push: settings.notify_on_force_push,
+            new_pr: settings.notify_on_new_pr,
+            pr_update: settings.notify_on_pr_update,
+            branch_delete: settings.notify_on_branch_delete,
+            tag_delete: settings.notify_on_tag_delete,
+            pr_close: settings.notify_on_pr_close,
+            tag_changelog: settings.include_tag_changelog,
+            release_filter: settings.release_filter.as_str().to_string(),
+            delivery_mode: settings.delivery_mode.map(|mode| mode.as_str().to_string()),
+        }
+    }
+}
+
+impl ExportedSettings {
+    fn to_settings(&self) -> Result<SubscriptionSettings, String> {
+        let release_filter = ReleaseFilter::from_db(&self.release_filter)
+            .ok_or_else(|| format!("unknown release filter {}", self.release_filter))?;
+        let delivery_mode = match &self.delivery_mode {
+            Some(mode) => Some(
+                DeliveryMode::from_db(mode)
+                    .filter(|mode| *mode != DeliveryMode::AfterQuietHours)
+                    .ok_or_else(|| format!("unknown delivery mode {}", mode))?,
+            ),
+            None => None,
+        };
+
+        Ok(SubscriptionSettings {
+            notify_on_new_branch: self.new_branch,
+            notify_on_new_tag: self.new_tag,
+            notify_on_branch_update: self.branch_update,
+            notify_on_new_pr: self.new_pr,
+            notify_on_pr_update: self.pr_update,
+            notify_on_force_push: self.force_push,
+            notify_on_branch_delete: self.branch_delete,
+            notify_on_tag_delete: self.tag_delete,
+            notify_on_pr_close: self.pr_close,
+            include_tag_changelog: self.tag_changelog,
+            release_filter,
+            delivery_mode,
+        })
+    }
+}
+
+impl ExportedFilter {
+    fn parse(&self) -> Result<(RefKind, FilterMode), String> {
+        let ref_kind = RefKind::from_db(&self.ref_kind)
+            .ok_or_else(|| format!("unknown ref kind {}", self.ref_kind))?;
+        let mode = FilterMode::from_db(&self.mode)
+            .ok_or_else(|| format!("unknown filter mode {}", self.mode))?;
+        filters::compile_pattern(&self.pattern)
+            .map_err(|e| format!("invalid pattern {}: {}", self.pattern, e))?;
+        Ok((ref_kind, mode))
+    }
+}
+
+pub async fn export_subscriptions(
+    pool: &DbPool,
+    chat_id: i64,
+) -> Result<SubscriptionExport, DbError> {
+    let mut repositories = Vec::new();
+
+    for repo in db::get_chat_subscriptions(pool, chat_id).await? {
+        let settings = db::get_subscription_settings(pool, chat_id, repo.id).await?;
+        let filters = db::get_subscription_filters(pool, chat_id, repo.id).await?;
+        repositories.push(ExportedRepository {
+            url: repo.url,
+            settings: Some(ExportedSettings::from(&settings)),
+            filters: filters
+                .into_iter()
+                .map(|filter| ExportedFilter {
+                    ref_kind: filter.ref_kind.as_str().to_string(),
+                    mode: filter.mode.as_str().to_string(),
+                    pattern: filter.pattern,
+                })
+                .collect(),
+        });
+    }
+
+    Ok(SubscriptionExport {
+        version: EXPORT_VERSION,
+        repositories,
+    })
+}
+
+pub async fn import_repository(
+    pool: &DbPool,
+    chat: &Chat,
+    thread_id: Option<i32>,
+    exported: &ExportedRepository,
+) -> ImportOutcome {
+    let settings = match exported.settings.as_ref().map(ExportedSettings::to_settings) {
+        Some(Ok(settings)) => Some(settings),
+        Some(Err(reason)) => return ImportOutcome::Invalid(reason),
+        None => None,
+    };
+    let mut new_filters = Vec::new();
+    for filter in &exported.filters {
+        match filter.parse() {
+            Ok((ref_kind, mode)) => new_filters.push((ref_kind, mode, filter.pattern.as_str())),
+            Err(reason) => return ImportOutcome::Invalid(reason),
+        }
+    }
+
+    let url = repo_url::canonicalize(&exported.url);
+    if let Err(e) = git_service::ls_remote(&url, None).await {
+        log::warn!("Failed to ls_remote for {}: {:?}", url, e);
+        return ImportOutcome::Unreachable;
+    }
+
+    match restore_subscription(pool, chat, thread_id, &url, settings, &new_filters).await {
+        Ok(()) => ImportOutcome::Imported,
+        Err(e) => {
+            log::error!("Database error while importing {}: {:?}", url, e);
+            ImportOutcome::Failed
+        }
+    }
+}
+
+async fn restore_subscription(
+    pool: &DbPool,
+    chat: &Chat,
+    thread_id: Option<i32>,
+    url: &str,
+    settings: Option<SubscriptionSettings>,
+    new_filters: &[(RefKind, FilterMode, &str)],
+) -> Result<(), DbError> {
+    let repo_id = db::add_repository_subscription(pool, chat, thread_id, url).await?;
+    if let Some(settings) = settings {
+        db::update_subscription_settings(pool, chat.id.0, repo_id, &settings).await?;
+    }
+
+    // Importing the same file twice must not duplicate filters.
+    let existing = db::get_subscription_filters(pool, chat.id.0, repo_id).await?;
+    for (ref_kind, mode, pattern) in new_filters {
+        let exists = existing.iter().any(|filter| {
+            filter.ref_kind == *ref_kind && filter.mode == *mode && filter.pattern == *pattern
+        });
+        if !exists {
+            db::add_subscription_filter(pool, chat.id.0, repo_id, *ref_kind, *mode, pattern)
+                .await?;
+        }
+    }
+    Ok(())
+}
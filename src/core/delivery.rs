@@ -0,0 +1,206 @@
+use crate::bot::AppBot;
+use crate::infrastructure::db::{self, DbPool, OutboxMessage};
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardMarkup, ParseMode};
+use teloxide::{ApiError, RequestError};
+
+const BATCH_SIZE: u32 = 50;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+// With the delay capped at an hour this keeps retrying for about half a day.
+const MAX_ATTEMPTS: i32 = 20;
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub chat_id: ChatId,
+    pub text: String,
+    pub reply_markup: Option<InlineKeyboardMarkup>,
+    pub thread_id: Option<i32>,
+    pub disable_notification: bool,
+    pub deliver_at: Option<DateTime<Utc>>,
+}
+
+impl Notification {
+    pub fn new(chat_id: ChatId, text: impl Into<String>) -> Self {
+        Notification {
+            chat_id,
+            text: text.into(),
+            reply_markup: None,
+            thread_id: None,
+            disable_notification: false,
+            deliver_at: None,
+        }
+    }
+
+    pub fn with_reply_markup(mut self, markup: InlineKeyboardMarkup) -> Self {
+        self.reply_markup = Some(markup);
+        self
+    }
+
+    pub fn in_thread(mut self, thread_id: Option<i32>) -> Self {
+        self.thread_id = thread_id;
+        self
+    }
+
+    pub fn silent(mut self) -> Self {
+        self.disable_notification = true;
+        self
+    }
+
+    pub fn deliver_at(mut self, at: DateTime<Utc>) -> Self {
+        self.deliver_at = Some(at);
+        self
+    }
+}
+
+pub async fn run_delivery_worker(bot: AppBot, pool: DbPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(2));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = deliver_pending(&bot, &pool).await {
+            log::error!("Error while delivering notifications: {:?}", e);
+        }
+    }
+}
+
+async fn deliver_pending(
+    bot: &AppBot,
+    pool: &DbPool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let pending = db::get_due_notifications(pool, BATCH_SIZE).await?;
+    let mut throttled_chats = HashSet::new();
+
+    for notification in pending {
+        let chat_id = ChatId(notification.chat_id);
+        if throttled_chats.contains(&chat_id) {
+            continue;
+        }
+        match send(bot, &notification).await {
+            Ok(()) => db::delete_notification(pool, notification.id).await?,
+            Err(RequestError::Api(ApiError::BotBlocked | ApiError::UserDeactivated)) => {
+                log::warn!("User {} has blocked the bot. Removing user.", chat_id);
+                db::remove_chat(pool, chat_id.0).await?;
+            }
+            Err(RequestError::Api(
+                ApiError::BotKicked | ApiError::BotKickedFromSupergroup | ApiError::GroupDeactivated,
+            )) => {
+                log::warn!("The bot is no longer a member of {}. Removing chat.", chat_id);
+                db::remove_chat(pool, chat_id.0).await?;
+            }
+            Err(RequestError::MigrateToChatId(new_chat_id)) => {
+                log::info!("Chat {} was migrated to {}", chat_id, new_chat_id);
+                db::migrate_chat(pool, chat_id.0, new_chat_id).await?;
+            }
+            Err(RequestError::RetryAfter(delay)) => {
+                log::warn!("Telegram asked to retry {} after {:?}", chat_id, delay);
+                db::postpone_chat_notifications(pool, chat_id.0, delay.as_secs().max(1)).await?;
+                throttled_chats.insert(chat_id);
+            }
+            Err(RequestError::Api(ApiError::Unknown(description)))
+                if notification.message_thread_id.is_some() && is_topic_deleted(&description) =>
+            {
+                log::warn!(
+                    "A forum topic of {} no longer exists, routing its updates to the main chat",
+                    chat_id
+                );
+                if let Some(thread_id) = notification.message_thread_id {
+                    db::forget_topic(pool, chat_id.0, thread_id).await?;
+                }
+            }
+            Err(RequestError::Api(ApiError::Unknown(description)))
+                if notification.message_thread_id.is_some() && is_topic_closed(&description) =>
+            {
+                log::warn!(
+                    "The forum topic of notification {} in {} is closed, sending it to the main chat",
+                    notification.id,
+                    chat_id
+                );
+                db::clear_notification_thread(pool, notification.id).await?;
+            }
+            Err(e) if is_undeliverable(&e) => {
+                log::error!(
+                    "Dropping notification {} for {} that can never be delivered: {:?}",
+                    notification.id,
+                    chat_id,
+                    e
+                );
+                db::delete_notification(pool, notification.id).await?;
+            }
+            Err(e) if notification.attempts + 1 >= MAX_ATTEMPTS => {
+                log::error!(
+                    "Dropping notification {} for {} after {} failed attempts: {:?}",
+                    notification.id,
+                    chat_id,
+                    notification.attempts + 1,
+                    e
+                );
+                db::delete_notification(pool, notification.id).await?;
+            }
+            Err(e) => {
+                let delay = retry_delay(notification.attempts);
+                log::warn!(
+                    "Failed to send notification {} to {} (attempt {}), retrying in {:?}: {:?}",
+                    notification.id,
+                    chat_id,
+                    notification.attempts + 1,
+                    delay,
+                    e
+                );
+                db::reschedule_notification(pool, notification.id, delay.as_secs()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn send(bot: &AppBot, notification: &OutboxMessage) -> Result<(), RequestError> {
+    let mut request = bot
+        .send_message(ChatId(notification.chat_id), &notification.message)
+        .parse_mode(ParseMode::MarkdownV2)
+        .disable_web_page_preview(true)
+        .disable_notification(notification.disable_notification);
+
+    if let Some(thread_id) = notification.message_thread_id {
+        request = request.message_thread_id(thread_id);
+    }
+
+    if let Some(markup) = &notification.reply_markup {
+        match serde_json::from_str::<InlineKeyboardMarkup>(markup) {
+            Ok(markup) => request = request.reply_markup(markup),
+            Err(e) => log::warn!("Ignoring malformed reply markup of {}: {:?}", notification.id, e),
+        }
+    }
+
+    request.await?;
+    Ok(())
+}
+
+fn is_undeliverable(error: &RequestError) -> bool {
+    matches!(
+        error,
+        RequestError::Api(
+            ApiError::ChatNotFound
+                | ApiError::CantInitiateConversation
+                | ApiError::CantParseEntities
+                | ApiError::MessageIsTooLong
+        )
+    )
+}
+
+// Telegram has no dedicated errors for forum topics, only these bad request descriptions.
+fn is_topic_deleted(description: &str) -> bool {
+    description.contains("message thread not found") || description.contains("TOPIC_DELETED")
+}
+
+fn is_topic_closed(description: &str) -> bool {
+    description.contains("TOPIC_CLOSED")
+}
+
+fn retry_delay(attempts: i32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.clamp(0, 16) as u32);
+    Duration::from_secs(5).saturating_mul(factor).min(MAX_RETRY_DELAY)
+}
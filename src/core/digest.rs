@@ -0,0 +1,238 @@
+use crate::core::delivery::Notification;
+use crate::core::events::GitEvent;
+use crate::core::forge::Forge;
+use crate::core::quiet_hours::{QuietHours, QuietHoursMode};
+use crate::core::updater::short_repo_name;
+use crate::infrastructure::db::{self, DbPool, DigestGroup, StoredDigestEntry};
+use chrono::{DateTime, Duration as ChronoDuration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use teloxide::types::ChatId;
+use teloxide::utils::markdown::escape;
+
+const MAX_MESSAGE_LENGTH: usize = 4000;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum DeliveryMode {
+    #[default]
+    Immediate,
+    Hourly,
+    Daily,
+    AfterQuietHours,
+}
+
+#[derive(Debug, Clone)]
+pub struct DigestEntry {
+    pub chat_id: ChatId,
+    pub repo_url: String,
+    pub event: GitEvent,
+    pub delivery_mode: DeliveryMode,
+    pub thread_id: Option<i32>,
+}
+
+impl DeliveryMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryMode::Immediate => "immediate",
+            DeliveryMode::Hourly => "hourly",
+            DeliveryMode::Daily => "daily",
+            DeliveryMode::AfterQuietHours => "quiet_hours",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<Self> {
+        match value {
+            "immediate" => Some(DeliveryMode::Immediate),
+            "hourly" => Some(DeliveryMode::Hourly),
+            "daily" => Some(DeliveryMode::Daily),
+            "quiet_hours" => Some(DeliveryMode::AfterQuietHours),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DeliveryMode::Immediate => "Immediate",
+            DeliveryMode::Hourly => "Hourly digest",
+            DeliveryMode::Daily => "Daily digest",
+            DeliveryMode::AfterQuietHours => "Quiet hours summary",
+        }
+    }
+
+    pub fn next_override(current: Option<DeliveryMode>) -> Option<DeliveryMode> {
+        match current {
+            None => Some(DeliveryMode::Immediate),
+            Some(DeliveryMode::Immediate) => Some(DeliveryMode::Hourly),
+            Some(DeliveryMode::Hourly) => Some(DeliveryMode::Daily),
+            Some(DeliveryMode::Daily | DeliveryMode::AfterQuietHours) => None,
+        }
+    }
+}
+
+pub async fn run_digest_scheduler(pool: DbPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = flush_due_digests(&pool).await {
+            log::error!("Error while sending digests: {:?}", e);
+        }
+    }
+}
+
+async fn flush_due_digests(pool: &DbPool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let now = Utc::now();
+
+    for group in db::get_pending_digest_groups(pool).await? {
+        let Some(mode) = DeliveryMode::from_db(&group.delivery_mode) else {
+            continue;
+        };
+        let quiet_mode = QuietHours::from_db(
+            group.quiet_hours_start,
+            group.quiet_hours_end,
+            &group.quiet_hours_mode,
+        )
+        .filter(|quiet_hours| quiet_hours.active_until(&group.timezone, now).is_some())
+        .map(|quiet_hours| quiet_hours.mode);
+        if matches!(
+            quiet_mode,
+            Some(QuietHoursMode::Hold | QuietHoursMode::Summarize)
+        ) || (mode == DeliveryMode::AfterQuietHours && quiet_mode.is_some())
+            || !is_due(&group, mode, now)
+        {
+            continue;
+        }
+
+        let entries =
+            db::get_digest_entries(pool, group.chat_id, mode, group.message_thread_id).await?;
+        let title = match mode {
+            DeliveryMode::Daily => "🗞 Daily digest",
+            DeliveryMode::AfterQuietHours => "🌙 While you were away",
+            _ => "🗞 Hourly digest",
+        };
+        let notifications: Vec<_> = render_digest(title, &entries)
+            .into_iter()
+            .map(|message| {
+                let notification = Notification::new(ChatId(group.chat_id), message)
+                    .in_thread(group.message_thread_id);
+                if quiet_mode == Some(QuietHoursMode::Silent) {
+                    notification.silent()
+                } else {
+                    notification
+                }
+            })
+            .collect();
+        let entry_ids: Vec<_> = entries.iter().map(|entry| entry.id).collect();
+
+        log::info!(
+            "Sending {} digest with {} events to {}",
+            mode.as_str(),
+            entry_ids.len(),
+            group.chat_id
+        );
+        db::commit_digest(pool, &entry_ids, &notifications).await?;
+    }
+    Ok(())
+}
+
+fn is_due(group: &DigestGroup, mode: DeliveryMode, now: DateTime<Utc>) -> bool {
+    match mode {
+        DeliveryMode::Immediate | DeliveryMode::AfterQuietHours => true,
+        DeliveryMode::Hourly => now - group.oldest_entry_at >= ChronoDuration::hours(1),
+        DeliveryMode::Daily => {
+            let tz: Tz = group.timezone.parse().unwrap_or(Tz::UTC);
+            group.oldest_entry_at < last_occurrence(tz, group.digest_time, now)
+        }
+    }
+}
+
+pub fn last_occurrence(tz: Tz, at: NaiveTime, now: DateTime<Utc>) -> DateTime<Utc> {
+    let local_now = now.with_timezone(&tz);
+    let mut date = local_now.date_naive();
+    if local_now.time() < at {
+        date = date.pred_opt().unwrap_or(date);
+    }
+    let local = date.and_time(at);
+    tz.from_local_datetime(&local)
+        .earliest()
+        .map(|scheduled| scheduled.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&local))
+}
+
+pub fn render_digest(title: &str, entries: &[StoredDigestEntry]) -> Vec<String> {
+    let mut by_repo: BTreeMap<&str, Vec<GitEvent>> = BTreeMap::new();
+    for entry in entries {
+        match serde_json::from_str::<GitEvent>(&entry.event) {
+            Ok(event) => by_repo.entry(&entry.repo_url).or_default().push(event),
+            Err(e) => log::warn!("Skipping malformed digest entry {}: {:?}", entry.id, e),
+        }
+    }
+
+    let event_count: usize = by_repo.values().map(Vec::len).sum();
+    let header = format!("*{}* \\({} events\\)", escape(title), event_count);
+    let mut messages = Vec::new();
+    let mut current = header.clone();
+
+    for (repo_url, events) in by_repo {
+        let forge = Forge::detect(repo_url);
+        let heading = format!(
+            "\n\n📦 [{}]({})",
+            escape(&short_repo_name(forge.base_url())),
+            escape(forge.base_url())
+        );
+        let lines: Vec<String> = events
+            .iter()
+            .filter_map(GitEvent::render_as_notification)
+            .map(|line| format!("\n{}", line))
+            .collect();
+
+        let section_length =
+            visible_length(&heading) + lines.iter().map(|line| visible_length(line)).sum::<usize>();
+        if visible_length(&current) + section_length > MAX_MESSAGE_LENGTH && current != header {
+            messages.push(std::mem::replace(&mut current, header.clone()));
+        }
+
+        // A section too long for one message carries on under its heading in the next.
+        let fresh = format!("{}{}", header, heading);
+        current.push_str(&heading);
+        for line in lines {
+            if visible_length(&current) + visible_length(&line) > MAX_MESSAGE_LENGTH
+                && current != fresh
+            {
+                messages.push(std::mem::replace(&mut current, fresh.clone()));
+            }
+            current.push_str(&line);
+        }
+    }
+    messages.push(current);
+    messages
+}
+
+// Telegram limits the text left once MarkdownV2 is parsed, counted in UTF-16 units,
+// so escapes, emphasis markers and link targets don't count towards it.
+fn visible_length(markdown: &str) -> usize {
+    let mut length = 0;
+    let mut chars = markdown.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => length += chars.next().map_or(0, char::len_utf16),
+            '*' | '_' | '~' | '|' | '`' | '[' => {}
+            ']' if chars.peek() == Some(&'(') => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        ')' => break,
+                        _ => {}
+                    }
+                }
+            }
+            c => length += c.len_utf16(),
+        }
+    }
+    length
+}
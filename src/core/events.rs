@@ -1,3 +1,5 @@
+use crate::core::filters::RefKind;
+use crate::core::releases::Release;
 use serde::{Deserialize, Serialize};
 use teloxide::utils::markdown::escape;
 
@@ -7,16 +9,92 @@ pub struct Branch {
     pub sha: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Tag {
     pub name: String,
     pub sha: String,
+    pub release: Option<Release>,
+    // Annotated tags point at a tag object, this is the commit it was peeled to.
+    #[serde(default)]
+    pub commit_sha: Option<String>,
+    #[serde(default)]
+    pub annotation: Option<TagAnnotation>,
+    #[serde(default)]
+    pub previous_tag: Option<String>,
+    #[serde(default)]
+    pub commits: Vec<Commit>,
+    #[serde(default)]
+    pub commit_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct TagAnnotation {
+    pub tagger: String,
+    pub tagged_at: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ReviewKind {
+    #[default]
+    PullRequest,
+    MergeRequest,
+    Change,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct PullRequest {
     pub id: u64,
     pub sha: String,
+    #[serde(default)]
+    pub kind: ReviewKind,
+    #[serde(default)]
+    pub patchset: Option<u32>,
+    // The forge's trial merge of the head into the base branch, when it published one.
+    #[serde(default)]
+    pub merge_sha: Option<String>,
+    #[serde(default)]
+    pub mergeable: Option<bool>,
+    #[serde(default)]
+    pub diff: Option<PullRequestDiff>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct PullRequestDiff {
+    pub base_sha: String,
+    pub commit_count: usize,
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+// A ref a forge publishes for code under review:
+// GitHub "refs/pull/N/head|merge", GitLab "refs/merge-requests/N/head|merge"
+// and Gerrit "refs/changes/NN/CHANGE/PATCHSET".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReviewRef {
+    pub kind: ReviewKind,
+    pub id: u64,
+    pub patchset: Option<u32>,
+    pub is_merge: bool,
+}
+
+// Every ref the bot follows, classified in one place instead of by prefix checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackedRef<'a> {
+    Branch(&'a str),
+    Tag(&'a str),
+    Review(ReviewRef),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Commit {
+    pub sha: String,
+    pub author: String,
+    pub summary: String,
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -27,13 +105,145 @@ pub enum GitEvent {
         name: String,
         old_sha: String,
         new_sha: String,
+        commits: Vec<Commit>,
+        commit_count: usize,
+        // The previous head couldn't be fetched, so a force-push can't be ruled out.
+        #[serde(default)]
+        history_unknown: bool,
+    },
+    BranchForcePushed {
+        name: String,
+        old_sha: String,
+        new_sha: String,
+        commits: Vec<Commit>,
+        commit_count: usize,
     },
     NewPullRequest(PullRequest),
     PullRequestUpdated(PullRequest),
+    BranchDeleted(Branch),
+    TagDeleted(Tag),
+    PullRequestClosed(PullRequest),
     NoChanges,
 }
 
+impl ReviewKind {
+    pub fn noun(&self) -> &'static str {
+        match self {
+            ReviewKind::PullRequest => "Pull Request",
+            ReviewKind::MergeRequest => "Merge Request",
+            ReviewKind::Change => "Change",
+        }
+    }
+}
+
+impl ReviewRef {
+    pub fn parse(ref_name: &str) -> Option<Self> {
+        let parts: Vec<&str> = ref_name.split('/').collect();
+        match parts.as_slice() {
+            ["refs", "pull", id, suffix @ ("head" | "merge")] => Some(ReviewRef {
+                kind: ReviewKind::PullRequest,
+                id: id.parse().ok()?,
+                patchset: None,
+                is_merge: *suffix == "merge",
+            }),
+            ["refs", "merge-requests", id, suffix @ ("head" | "merge")] => Some(ReviewRef {
+                kind: ReviewKind::MergeRequest,
+                id: id.parse().ok()?,
+                patchset: None,
+                is_merge: *suffix == "merge",
+            }),
+            ["refs", "changes", _, id, patchset] => Some(ReviewRef {
+                kind: ReviewKind::Change,
+                id: id.parse().ok()?,
+                patchset: Some(patchset.parse().ok()?),
+                is_merge: false,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_pull_request(self, sha: &str) -> PullRequest {
+        PullRequest {
+            id: self.id,
+            sha: sha.to_string(),
+            kind: self.kind,
+            patchset: self.patchset,
+            merge_sha: None,
+            mergeable: None,
+            diff: None,
+        }
+    }
+}
+
+impl<'a> TrackedRef<'a> {
+    // Peeled "^{}" entries only repeat an annotated tag, they aren't refs of their own.
+    pub fn parse(ref_name: &'a str) -> Option<Self> {
+        if let Some(name) = ref_name.strip_prefix("refs/heads/") {
+            Some(TrackedRef::Branch(name))
+        } else if let Some(name) = ref_name.strip_prefix("refs/tags/") {
+            (!name.ends_with("^{}")).then_some(TrackedRef::Tag(name))
+        } else {
+            ReviewRef::parse(ref_name).map(TrackedRef::Review)
+        }
+    }
+
+    // Trial merges are stored to follow mergeability but never announced themselves.
+    pub fn is_review_merge(&self) -> bool {
+        matches!(self, TrackedRef::Review(review) if review.is_merge)
+    }
+}
+
+impl PullRequest {
+    pub fn head_ref(&self) -> String {
+        match self.kind {
+            ReviewKind::PullRequest => format!("refs/pull/{}/head", self.id),
+            ReviewKind::MergeRequest => format!("refs/merge-requests/{}/head", self.id),
+            ReviewKind::Change => format!(
+                "refs/changes/{:02}/{}/{}",
+                self.id % 100,
+                self.id,
+                self.patchset.unwrap_or(1)
+            ),
+        }
+    }
+
+    pub fn merge_ref(&self) -> Option<String> {
+        match self.kind {
+            ReviewKind::PullRequest => Some(format!("refs/pull/{}/merge", self.id)),
+            ReviewKind::MergeRequest => Some(format!("refs/merge-requests/{}/merge", self.id)),
+            ReviewKind::Change => None,
+        }
+    }
+
+    // "#12" on GitHub-like forges, "!12" for GitLab merge requests, "12345,3" for Gerrit.
+    pub fn display_id(&self) -> String {
+        match (self.kind, self.patchset) {
+            (ReviewKind::MergeRequest, _) => format!("!{}", self.id),
+            (ReviewKind::Change, Some(patchset)) => format!("{},{}", self.id, patchset),
+            _ => format!("#{}", self.id),
+        }
+    }
+}
+
 impl GitEvent {
+    pub fn filter_target(&self) -> Option<(RefKind, &str)> {
+        match self {
+            GitEvent::NewBranch(Branch { name, .. })
+            | GitEvent::BranchDeleted(Branch { name, .. })
+            | GitEvent::BranchUpdated { name, .. }
+            | GitEvent::BranchForcePushed { name, .. } => {
+                Some((RefKind::Branch, name.trim_start_matches("refs/heads/")))
+            }
+            GitEvent::NewTag(Tag { name, .. }) | GitEvent::TagDeleted(Tag { name, .. }) => {
+                Some((RefKind::Tag, name.trim_start_matches("refs/tags/")))
+            }
+            GitEvent::NewPullRequest(_)
+            | GitEvent::PullRequestUpdated(_)
+            | GitEvent::PullRequestClosed(_)
+            | GitEvent::NoChanges => None,
+        }
+    }
+
     pub fn render_as_notification(&self) -> Option<String> {
         match self {
             GitEvent::NewBranch(branch) => {
@@ -48,11 +258,141 @@ impl GitEvent {
                 let branch_name = name.trim_start_matches("refs/heads/");
                 Some(format!("🚀 Branch Updated: *{}*", escape(branch_name)))
             }
-            GitEvent::NewPullRequest(pr) => Some(format!("📦 New Pull Request: *\\#{}*", pr.id)),
-            GitEvent::PullRequestUpdated(pr) => {
-                Some(format!("📥 Pull Request Updated: *\\#{}*", pr.id))
+            GitEvent::BranchForcePushed { name, .. } => {
+                let branch_name = name.trim_start_matches("refs/heads/");
+                Some(format!("⚠️ Branch Force\\-Pushed: *{}*", escape(branch_name)))
+            }
+            GitEvent::NewPullRequest(pr) => Some(format!(
+                "📦 New {}: *{}*",
+                pr.kind.noun(),
+                escape(&pr.display_id())
+            )),
+            GitEvent::PullRequestUpdated(pr) => Some(format!(
+                "📥 {} Updated: *{}*",
+                pr.kind.noun(),
+                escape(&pr.display_id())
+            )),
+            GitEvent::BranchDeleted(branch) => {
+                let branch_name = branch.name.trim_start_matches("refs/heads/");
+                Some(format!("🗑️ Branch Deleted: *{}*", escape(branch_name)))
             }
+            GitEvent::TagDeleted(tag) => {
+                let tag_name = tag.name.trim_start_matches("refs/tags/");
+                Some(format!("🗑️ Tag Deleted: *{}*", escape(tag_name)))
+            }
+            GitEvent::PullRequestClosed(pr) => Some(format!(
+                "🔒 {} Closed: *{}*",
+                pr.kind.noun(),
+                escape(&pr.display_id())
+            )),
             GitEvent::NoChanges => None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn github_and_gitlab_review_refs() {
+        assert_eq!(
+            ReviewRef::parse("refs/pull/42/head"),
+            Some(ReviewRef {
+                kind: ReviewKind::PullRequest,
+                id: 42,
+                patchset: None,
+                is_merge: false,
+            })
+        );
+        assert_eq!(
+            ReviewRef::parse("refs/merge-requests/7/merge"),
+            Some(ReviewRef {
+                kind: ReviewKind::MergeRequest,
+                id: 7,
+                patchset: None,
+                is_merge: true,
+            })
+        );
+    }
+
+    #[test]
+    fn gerrit_change_refs() {
+        assert_eq!(
+            ReviewRef::parse("refs/changes/45/12345/3"),
+            Some(ReviewRef {
+                kind: ReviewKind::Change,
+                id: 12345,
+                patchset: Some(3),
+                is_merge: false,
+            })
+        );
+        // The change's notes ref isn't a patchset.
+        assert_eq!(ReviewRef::parse("refs/changes/45/12345/meta"), None);
+    }
+
+    #[test]
+    fn malformed_review_refs_are_ignored() {
+        for ref_name in [
+            "refs/pull/42",
+            "refs/pull/abc/head",
+            "refs/pull/42/head/extra",
+            "refs/pull/42/files",
+            "refs/merge-requests/7/train",
+            "refs/changes/45/12345",
+            "refs/heads/pull/42/head",
+        ] {
+            assert_eq!(ReviewRef::parse(ref_name), None, "{}", ref_name);
+        }
+    }
+
+    #[test]
+    fn review_refs_round_trip_through_pull_requests() {
+        for ref_name in [
+            "refs/pull/42/head",
+            "refs/merge-requests/7/head",
+            "refs/changes/45/12345/3",
+        ] {
+            let pr = ReviewRef::parse(ref_name).unwrap().to_pull_request("abc");
+            assert_eq!(pr.head_ref(), ref_name);
+        }
+        let pr = ReviewRef::parse("refs/pull/42/merge")
+            .unwrap()
+            .to_pull_request("abc");
+        assert_eq!(pr.merge_ref().as_deref(), Some("refs/pull/42/merge"));
+    }
+
+    #[test]
+    fn tracked_refs_are_classified() {
+        assert_eq!(
+            TrackedRef::parse("refs/heads/feature/x"),
+            Some(TrackedRef::Branch("feature/x"))
+        );
+        assert_eq!(
+            TrackedRef::parse("refs/tags/v1.0.0"),
+            Some(TrackedRef::Tag("v1.0.0"))
+        );
+        assert!(matches!(
+            TrackedRef::parse("refs/pull/1/head"),
+            Some(TrackedRef::Review(ReviewRef { id: 1, .. }))
+        ));
+        assert!(
+            TrackedRef::parse("refs/pull/1/merge").is_some_and(|tracked| tracked.is_review_merge())
+        );
+        assert!(
+            !TrackedRef::parse("refs/pull/1/head").is_some_and(|tracked| tracked.is_review_merge())
+        );
+    }
+
+    #[test]
+    fn untracked_refs_are_skipped() {
+        for ref_name in [
+            "HEAD",
+            "refs/tags/v1.0.0^{}",
+            "refs/notes/commits",
+            "refs/remotes/origin/main",
+        ] {
+            assert_eq!(TrackedRef::parse(ref_name), None, "{}", ref_name);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// Patterns are stored in a VARCHAR(255) column.
+pub const MAX_PATTERN_LENGTH: usize = 255;
+
+#[derive(Debug, Error)]
+pub enum PatternError {
+    #[error("patterns can be at most {} characters long", MAX_PATTERN_LENGTH)]
+    TooLong,
+    #[error("a [ character class is never closed")]
+    UnterminatedClass,
+    #[error("{0}")]
+    Regex(#[from] regex::Error),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum RefKind {
+    Branch,
+    Tag,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum FilterMode {
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefFilter {
+    pub id: i32,
+    pub ref_kind: RefKind,
+    pub mode: FilterMode,
+    pub pattern: String,
+}
+
+pub struct CompiledFilter {
+    ref_kind: RefKind,
+    mode: FilterMode,
+    regex: Regex,
+}
+
+impl RefKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefKind::Branch => "branch",
+            RefKind::Tag => "tag",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<Self> {
+        match value {
+            "branch" => Some(RefKind::Branch),
+            "tag" => Some(RefKind::Tag),
+            _ => None,
+        }
+    }
+}
+
+impl FilterMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterMode::Include => "include",
+            FilterMode::Exclude => "exclude",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<Self> {
+        match value {
+            "include" => Some(FilterMode::Include),
+            "exclude" => Some(FilterMode::Exclude),
+            _ => None,
+        }
+    }
+}
+
+// Patterns are globs by default (`*` stays within one path segment, `**` crosses
+// segments, `?` and `[...]` behave as usual); a `re:` prefix switches to a regex.
+pub fn compile_pattern(pattern: &str) -> Result<Regex, PatternError> {
+    if pattern.chars().count() > MAX_PATTERN_LENGTH {
+        return Err(PatternError::TooLong);
+    }
+    if let Some(expr) = pattern.strip_prefix("re:") {
+        return Ok(Regex::new(&format!("^(?:{})$", expr))?);
+    }
+
+    let mut expr = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                expr.push_str(".*");
+            }
+            '*' => expr.push_str("[^/]*"),
+            '?' => expr.push_str("[^/]"),
+            '[' => {
+                let negated = chars.next_if_eq(&'!').is_some();
+                // A `]` right after the opening bracket is a member, not the end.
+                let mut members: Vec<char> = chars.next_if_eq(&']').into_iter().collect();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c) => members.push(c),
+                        None => return Err(PatternError::UnterminatedClass),
+                    }
+                }
+                expr.push_str(if negated { "[^" } else { "[" });
+                let mut i = 0;
+                while i < members.len() {
+                    push_class_member(&mut expr, members[i]);
+                    if members.get(i + 1) == Some(&'-') && i + 2 < members.len() {
+                        expr.push('-');
+                        push_class_member(&mut expr, members[i + 2]);
+                        i += 3;
+                    } else {
+                        i += 1;
+                    }
+                }
+                expr.push(']');
+            }
+            _ => expr.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expr.push('$');
+    Ok(Regex::new(&expr)?)
+}
+
+// Inside a regex class these start nested classes, set operations or ranges.
+fn push_class_member(expr: &mut String, c: char) {
+    if matches!(c, '[' | ']' | '\\' | '&' | '-' | '~' | '^') {
+        expr.push('\\');
+    }
+    expr.push(c);
+}
+
+// Patterns were validated when they were saved, one that no longer compiles is skipped.
+pub fn compile_filters(filters: &[RefFilter]) -> Vec<CompiledFilter> {
+    filters
+        .iter()
+        .filter_map(|filter| match compile_pattern(&filter.pattern) {
+            Ok(regex) => Some(CompiledFilter {
+                ref_kind: filter.ref_kind,
+                mode: filter.mode,
+                regex,
+            }),
+            Err(e) => {
+                log::warn!("Skipping invalid filter pattern {:?}: {}", filter.pattern, e);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn ref_passes(filters: &[CompiledFilter], ref_kind: RefKind, ref_name: &str) -> bool {
+    let mut has_include = false;
+    let mut included = false;
+
+    for filter in filters.iter().filter(|f| f.ref_kind == ref_kind) {
+        let matches = filter.regex.is_match(ref_name);
+        match filter.mode {
+            FilterMode::Include => {
+                has_include = true;
+                included |= matches;
+            }
+            FilterMode::Exclude if matches => return false,
+            FilterMode::Exclude => {}
+        }
+    }
+
+    !has_include || included
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(ref_kind: RefKind, mode: FilterMode, pattern: &str) -> RefFilter {
+        RefFilter {
+            id: 0,
+            ref_kind,
+            mode,
+            pattern: pattern.to_string(),
+        }
+    }
+
+    #[test]
+    fn single_star_stays_within_a_segment() {
+        let re = compile_pattern("release/*").unwrap();
+        assert!(re.is_match("release/1.0"));
+        assert!(!re.is_match("release/1.0/hotfix"));
+        assert!(!re.is_match("prerelease/1.0"));
+    }
+
+    #[test]
+    fn double_star_crosses_segments() {
+        let re = compile_pattern("feature/**").unwrap();
+        assert!(re.is_match("feature/a/b/c"));
+        assert!(!re.is_match("bugfix/a"));
+    }
+
+    #[test]
+    fn question_mark_and_dots_are_literal_where_expected() {
+        let re = compile_pattern("v?.0").unwrap();
+        assert!(re.is_match("v1.0"));
+        assert!(!re.is_match("v1x0"));
+        assert!(!re.is_match("v/.0"));
+    }
+
+    #[test]
+    fn character_classes_and_negation() {
+        let re = compile_pattern("v[0-9]").unwrap();
+        assert!(re.is_match("v7"));
+        assert!(!re.is_match("vx"));
+
+        let re = compile_pattern("[!_]*").unwrap();
+        assert!(re.is_match("main"));
+        assert!(!re.is_match("_wip"));
+    }
+
+    #[test]
+    fn class_members_are_literal() {
+        let re = compile_pattern("[a&&b]").unwrap();
+        assert!(re.is_match("&"));
+        assert!(re.is_match("a"));
+        assert!(!re.is_match("c"));
+
+        let re = compile_pattern("[-a-]").unwrap();
+        assert!(re.is_match("-"));
+        assert!(re.is_match("a"));
+        assert!(!re.is_match("b"));
+        // A range from `a` down to `-`, not a set difference.
+        assert!(matches!(compile_pattern("[a--b]"), Err(PatternError::Regex(_))));
+
+        let re = compile_pattern("[a~~b^]").unwrap();
+        assert!(re.is_match("~"));
+        assert!(re.is_match("^"));
+        assert!(!re.is_match("c"));
+    }
+
+    #[test]
+    fn brackets_inside_classes() {
+        let re = compile_pattern("[[]").unwrap();
+        assert!(re.is_match("["));
+
+        let re = compile_pattern("[]x]").unwrap();
+        assert!(re.is_match("]"));
+        assert!(re.is_match("x"));
+        assert!(!re.is_match("y"));
+
+        let re = compile_pattern("[!]]").unwrap();
+        assert!(!re.is_match("]"));
+        assert!(re.is_match("a"));
+    }
+
+    #[test]
+    fn unterminated_classes_are_rejected() {
+        assert!(matches!(compile_pattern("[abc"), Err(PatternError::UnterminatedClass)));
+        assert!(matches!(compile_pattern("v[]"), Err(PatternError::UnterminatedClass)));
+        assert!(compile_pattern("v[]]").is_ok());
+    }
+
+    #[test]
+    fn regex_prefix_is_anchored() {
+        let re = compile_pattern(r"re:v[0-9]+\..*").unwrap();
+        assert!(re.is_match("v12.3"));
+        assert!(!re.is_match("xv12.3"));
+        assert!(compile_pattern("re:(").is_err());
+    }
+
+    #[test]
+    fn overlong_patterns_are_rejected() {
+        let pattern = "a".repeat(MAX_PATTERN_LENGTH + 1);
+        assert!(matches!(compile_pattern(&pattern), Err(PatternError::TooLong)));
+        assert!(compile_pattern(&pattern[1..]).is_ok());
+    }
+
+    #[test]
+    fn excludes_win_over_includes() {
+        let filters = compile_filters(&[
+            filter(RefKind::Branch, FilterMode::Include, "release/*"),
+            filter(RefKind::Branch, FilterMode::Exclude, "release/old"),
+            filter(RefKind::Tag, FilterMode::Include, "v*"),
+        ]);
+        assert!(ref_passes(&filters, RefKind::Branch, "release/1.0"));
+        assert!(!ref_passes(&filters, RefKind::Branch, "release/old"));
+        assert!(!ref_passes(&filters, RefKind::Branch, "main"));
+        assert!(ref_passes(&filters, RefKind::Tag, "v1.0"));
+        assert!(!ref_passes(&filters, RefKind::Tag, "nightly"));
+    }
+}
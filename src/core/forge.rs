@@ -0,0 +1,222 @@
+use crate::core::events::{PullRequest, ReviewKind};
+use crate::infrastructure::config;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+static HOST_OVERRIDES: OnceLock<HashMap<String, ForgeKind>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForgeKind {
+    GitHub,
+    GitLab,
+    Gitea,
+    Bitbucket,
+    SourceHut,
+    Gerrit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forge {
+    pub kind: ForgeKind,
+    base_url: String,
+}
+
+impl ForgeKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "github" => Some(ForgeKind::GitHub),
+            "gitlab" => Some(ForgeKind::GitLab),
+            "gitea" | "forgejo" => Some(ForgeKind::Gitea),
+            "bitbucket" => Some(ForgeKind::Bitbucket),
+            "sourcehut" | "srht" => Some(ForgeKind::SourceHut),
+            "gerrit" => Some(ForgeKind::Gerrit),
+            _ => None,
+        }
+    }
+
+    fn for_host(host: &str) -> Self {
+        if let Some(kind) = host_override(host) {
+            return kind;
+        }
+        match host {
+            "gitlab.com" => ForgeKind::GitLab,
+            "codeberg.org" | "gitea.com" => ForgeKind::Gitea,
+            "bitbucket.org" => ForgeKind::Bitbucket,
+            "git.sr.ht" => ForgeKind::SourceHut,
+            _ if host.starts_with("gitlab.") => ForgeKind::GitLab,
+            _ if host.starts_with("gitea.") || host.starts_with("forgejo.") => ForgeKind::Gitea,
+            _ if host.starts_with("gerrit.") || host.starts_with("review.") => ForgeKind::Gerrit,
+            _ => ForgeKind::GitHub,
+        }
+    }
+}
+
+// Self-hosted instances are declared as FORGE_HOSTS=git.example.com=gitea,code.example.org=gitlab.
+// Links are rendered for every notification, so the list is only parsed once.
+fn host_override(host: &str) -> Option<ForgeKind> {
+    HOST_OVERRIDES
+        .get_or_init(|| parse_host_overrides(&config::env_or("FORGE_HOSTS", String::new())))
+        .get(&host.to_lowercase())
+        .copied()
+}
+
+fn parse_host_overrides(value: &str) -> HashMap<String, ForgeKind> {
+    value
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(host, kind)| {
+                Some((host.trim().to_lowercase(), ForgeKind::from_name(kind)?))
+            });
+            if parsed.is_none() {
+                log::warn!("Ignoring invalid FORGE_HOSTS entry {:?}", entry);
+            }
+            parsed
+        })
+        .collect()
+}
+
+impl Forge {
+    pub fn detect(repo_url: &str) -> Self {
+        let mut base_url = web_url(repo_url);
+        let host = base_url
+            .split_once("://")
+            .map(|(_, rest)| rest.split('/').next().unwrap_or(rest))
+            .unwrap_or_default();
+        let host = host.split(':').next().unwrap_or(host);
+        let kind = ForgeKind::for_host(host);
+
+        // Gerrit serves authenticated clones under "/a/", the web UI doesn't use it.
+        if kind == ForgeKind::Gerrit {
+            let (origin, project) = split_origin(&base_url);
+            if let Some(project) = project.strip_prefix("a/") {
+                base_url = format!("{}/{}", origin, project);
+            }
+        }
+
+        Forge { kind, base_url }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn branch_url(&self, branch: &str) -> String {
+        match self.kind {
+            ForgeKind::GitHub | ForgeKind::SourceHut => format!("{}/tree/{}", self.base_url, branch),
+            ForgeKind::GitLab => format!("{}/-/tree/{}", self.base_url, branch),
+            ForgeKind::Gitea => format!("{}/src/branch/{}", self.base_url, branch),
+            ForgeKind::Bitbucket => format!("{}/src/{}", self.base_url, branch),
+            ForgeKind::Gerrit => self.gitiles_url(&format!("+/refs/heads/{}", branch)),
+        }
+    }
+
+    pub fn tag_url(&self, tag: &str) -> String {
+        match self.kind {
+            ForgeKind::GitHub | ForgeKind::Gitea => format!("{}/releases/tag/{}", self.base_url, tag),
+            ForgeKind::GitLab => format!("{}/-/tags/{}", self.base_url, tag),
+            ForgeKind::Bitbucket => format!("{}/src/{}", self.base_url, tag),
+            ForgeKind::SourceHut => format!("{}/refs/{}", self.base_url, tag),
+            ForgeKind::Gerrit => self.gitiles_url(&format!("+/refs/tags/{}", tag)),
+        }
+    }
+
+    pub fn commit_url(&self, sha: &str) -> String {
+        match self.kind {
+            ForgeKind::GitHub | ForgeKind::Gitea | ForgeKind::SourceHut => {
+                format!("{}/commit/{}", self.base_url, sha)
+            }
+            ForgeKind::GitLab => format!("{}/-/commit/{}", self.base_url, sha),
+            ForgeKind::Bitbucket => format!("{}/commits/{}", self.base_url, sha),
+            ForgeKind::Gerrit => self.gitiles_url(&format!("+/{}", sha)),
+        }
+    }
+
+    // sourcehut has no compare view, so its link shows the log leading up to the new commit.
+    pub fn compare_url(&self, old_sha: &str, new_sha: &str) -> String {
+        match self.kind {
+            ForgeKind::GitHub | ForgeKind::Gitea => {
+                format!("{}/compare/{}...{}", self.base_url, old_sha, new_sha)
+            }
+            ForgeKind::GitLab => format!("{}/-/compare/{}...{}", self.base_url, old_sha, new_sha),
+            ForgeKind::Bitbucket => {
+                format!("{}/branches/compare/{}..{}", self.base_url, new_sha, old_sha)
+            }
+            ForgeKind::SourceHut => format!("{}/log/{}", self.base_url, new_sha),
+            ForgeKind::Gerrit => self.gitiles_url(&format!("+log/{}..{}", old_sha, new_sha)),
+        }
+    }
+
+    // The ref layout already tells merge requests and Gerrit changes apart, even on
+    // self-hosted instances nobody listed in FORGE_HOSTS. sourcehut reviews patches
+    // on mailing lists, so there is nothing to link to.
+    pub fn review_url(&self, pr: &PullRequest) -> Option<String> {
+        let (origin, project) = split_origin(&self.base_url);
+        match (pr.kind, self.kind) {
+            (ReviewKind::MergeRequest, _) | (ReviewKind::PullRequest, ForgeKind::GitLab) => {
+                Some(format!("{}/-/merge_requests/{}", self.base_url, pr.id))
+            }
+            (ReviewKind::Change, _) | (ReviewKind::PullRequest, ForgeKind::Gerrit) => {
+                let mut url = format!("{}/c/{}/+/{}", origin, project, pr.id);
+                if let Some(patchset) = pr.patchset {
+                    url.push_str(&format!("/{}", patchset));
+                }
+                Some(url)
+            }
+            (ReviewKind::PullRequest, ForgeKind::GitHub) => {
+                Some(format!("{}/pull/{}", self.base_url, pr.id))
+            }
+            (ReviewKind::PullRequest, ForgeKind::Gitea) => {
+                Some(format!("{}/pulls/{}", self.base_url, pr.id))
+            }
+            (ReviewKind::PullRequest, ForgeKind::Bitbucket) => {
+                Some(format!("{}/pull-requests/{}", self.base_url, pr.id))
+            }
+            (ReviewKind::PullRequest, ForgeKind::SourceHut) => None,
+        }
+    }
+
+    // Gerrit ships with Gitiles for browsing, mounted as a plugin.
+    fn gitiles_url(&self, view: &str) -> String {
+        let (origin, project) = split_origin(&self.base_url);
+        format!("{}/plugins/gitiles/{}/{}", origin, project, view)
+    }
+}
+
+// Splits "https://host/group/project" into "https://host" and "group/project".
+fn split_origin(base_url: &str) -> (&str, &str) {
+    let authority_start = base_url.find("://").map_or(0, |index| index + 3);
+    match base_url[authority_start..].find('/') {
+        Some(index) => {
+            let (origin, path) = base_url.split_at(authority_start + index);
+            (origin, &path[1..])
+        }
+        None => (base_url, ""),
+    }
+}
+
+// The browsable HTTPS address of a repository, whatever transport it is cloned over.
+fn web_url(repo_url: &str) -> String {
+    let url = repo_url.trim().trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_lowercase(), rest.to_string()),
+        // scp-like "git@host:owner/repo"
+        None => ("ssh".to_string(), url.replacen(':', "/", 1)),
+    };
+    let (authority, path) = rest.split_once('/').unwrap_or((&rest, ""));
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+
+    let authority = match scheme.as_str() {
+        "http" | "https" => host_port,
+        _ => host_port.split(':').next().unwrap_or(host_port),
+    };
+    let scheme = if scheme == "http" { "http" } else { "https" };
+
+    if path.is_empty() {
+        format!("{}://{}", scheme, authority)
+    } else {
+        format!("{}://{}/{}", scheme, authority, path)
+    }
+}
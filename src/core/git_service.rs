@@ -1,33 +1,457 @@
+use crate::core::credentials::Credentials;
+use crate::core::events::{Commit, PullRequestDiff, TagAnnotation, TrackedRef};
+use crate::infrastructure::config;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::env;
+use std::os::raw::c_int;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::task;
 
+const MAX_DESCRIBED_COMMITS: usize = 10;
+const INITIAL_FETCH_DEPTH: i32 = 200;
+const MAX_TAG_SEARCH_DEPTH: usize = 10_000;
+
+// git2 0.18 doesn't wrap libgit2 1.7's socket timeouts yet, these are their
+// positions in git_libgit2_opt_t.
+const GIT_OPT_SET_SERVER_CONNECT_TIMEOUT: c_int = 39;
+const GIT_OPT_SET_SERVER_TIMEOUT: c_int = 41;
+
+// SHA256 host key fingerprints as the forges publish them, so deploy keys work there
+// without a known_hosts file. Any other host key goes through ~/.ssh/known_hosts.
+const PINNED_HOST_KEYS: &[(&str, &str)] = &[
+    ("github.com", "+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU"),
+    ("github.com", "p2QAMXNIC1TJYWeIOttrVc98/R1BUFWu3/LiyKgUfQM"),
+    ("github.com", "uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s"),
+    ("gitlab.com", "eUXGGm1YGsMAS7vkcx6JOJdOGHPem5gQp4taiCfCLB8"),
+    ("gitlab.com", "HbW3g8zUjNSksFbqTiUWPWg2Bq1x8xdGUrliXFzSnUw"),
+    ("gitlab.com", "ROQFvPThGrW4RuWLoL9tq9I9zJ42fK4XywyRtbOz/EQ"),
+    ("bitbucket.org", "ybgmFkzwOSotHTHLJgHO0QN8L0xErw6vd0VhFA9m3SM"),
+    ("bitbucket.org", "FC73VB6C4OQLSCrjEayhMp9UMxS97caD/Yyi2bhW/J0"),
+    ("bitbucket.org", "46OSHA1Rmj8E8ERTC6xkNcmGOw9oFxYr0WF6zWW8l1E"),
+    ("codeberg.org", "mIlxA9k46MmM6qdJOdMnAQpzGxF4WIVVL+fj+wZbw0g"),
+    ("codeberg.org", "T9FYDEHELhVkulEKKwge5aVhVTbqCW0MIRwAfpARs/E"),
+    ("codeberg.org", "6QQmYi4ppFS4/+zSZ5S4IU+4sa6rwvQ4PbhCtPEBekQ"),
+];
+
 #[derive(Debug, Error)]
 pub enum GitServiceError {
     #[error("Git operation failed: {0}")]
     Git(#[from] git2::Error),
     #[error("Internal task execution error")]
     Task,
+    #[error("Remote did not respond within {0:?}")]
+    Timeout(Duration),
 }
 
-pub async fn ls_remote(url: &str) -> Result<HashMap<String, String>, GitServiceError> {
-    let url_owned = url.to_string();
-    task::spawn_blocking(move || {
-        let mut remote = git2::Remote::create_detached(url_owned.as_bytes())?;
-        remote.connect(git2::Direction::Fetch)?;
+pub struct CommitRange {
+    pub commits: Vec<Commit>,
+    pub total: usize,
+}
+
+pub struct TagDetails {
+    pub commit_sha: String,
+    pub annotation: Option<TagAnnotation>,
+}
+
+pub async fn ls_remote(
+    url: &str,
+    credentials: Option<&Credentials>,
+) -> Result<HashMap<String, String>, GitServiceError> {
+    let transport_url = transport_url(url, credentials);
+    let credentials = credentials.cloned();
+    let deadline = Instant::now() + network_timeout();
+    let task = task::spawn_blocking(move || {
+        let mut remote = git2::Remote::create_detached(transport_url.as_bytes())?;
+        remote.connect_auth(
+            git2::Direction::Fetch,
+            Some(remote_callbacks(credentials, deadline)),
+            None,
+        )?;
         let list = remote.list()?;
         let refs = list
             .iter()
-            .filter(|head| {
-                let name = head.name();
-                name.starts_with("refs/heads/")
-                    || name.starts_with("refs/tags/")
-                    || (name.starts_with("refs/pull/") && name.ends_with("/head"))
-            })
+            .filter(|head| TrackedRef::parse(head.name()).is_some())
             .map(|head| (head.name().to_string(), head.oid().to_string()))
             .collect();
         Ok(refs)
+    });
+    with_network_timeout(task).await
+}
+
+pub async fn fetch(
+    url: &str,
+    credentials: Option<&Credentials>,
+    refspecs: Vec<String>,
+) -> Result<(), GitServiceError> {
+    let url_owned = url.to_string();
+    let transport_url = transport_url(url, credentials);
+    let credentials = credentials.cloned();
+    let deadline = Instant::now() + network_timeout();
+    let task = task::spawn_blocking(move || {
+        let repo = open_cache(&url_owned)?;
+        let mut remote = repo.remote_anonymous(&transport_url)?;
+        let mut options = git2::FetchOptions::new();
+        options.remote_callbacks(remote_callbacks(credentials, deadline));
+        // The whole history of a large repository doesn't arrive within one timeout,
+        // an empty cache starts from the recent commits and later fetches add what follows.
+        if repo.references()?.next().is_none() {
+            options.depth(INITIAL_FETCH_DEPTH);
+        }
+        remote.fetch(&refspecs, Some(&mut options), None)?;
+        Ok(())
+    });
+    with_network_timeout(task).await
+}
+
+// A head that was rewritten away is no longer advertised, it can only be fetched by
+// id from servers that allow it. Returns whether the commit is now in the cache.
+pub async fn fetch_commit(
+    url: &str,
+    credentials: Option<&Credentials>,
+    sha: &str,
+) -> Result<bool, GitServiceError> {
+    let url_owned = url.to_string();
+    let transport_url = transport_url(url, credentials);
+    let credentials = credentials.cloned();
+    let sha = sha.to_string();
+    let deadline = Instant::now() + network_timeout();
+    let task = task::spawn_blocking(move || {
+        let repo = open_cache(&url_owned)?;
+        let oid = git2::Oid::from_str(&sha)?;
+        if repo.find_commit(oid).is_ok() {
+            return Ok(true);
+        }
+        let mut remote = repo.remote_anonymous(&transport_url)?;
+        let mut options = git2::FetchOptions::new();
+        options.remote_callbacks(remote_callbacks(credentials, deadline));
+        if let Err(e) = remote.fetch(&[sha.as_str()], Some(&mut options), None) {
+            log::debug!("Remote refused to send commit {}: {}", sha, e);
+        }
+        let fetched = repo.find_commit(oid).is_ok();
+        Ok(fetched)
+    });
+    with_network_timeout(task).await
+}
+
+pub async fn commit_range(
+    url: &str,
+    old_sha: &str,
+    new_sha: &str,
+) -> Result<CommitRange, GitServiceError> {
+    let url_owned = url.to_string();
+    let old_sha = old_sha.to_string();
+    let new_sha = new_sha.to_string();
+    task::spawn_blocking(move || {
+        let repo = open_cache(&url_owned)?;
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL)?;
+        walk.push(git2::Oid::from_str(&new_sha)?)?;
+        walk.hide(git2::Oid::from_str(&old_sha)?)?;
+
+        let mut commits = Vec::new();
+        let mut total = 0;
+        for oid in walk {
+            let oid = oid?;
+            if commits.len() < MAX_DESCRIBED_COMMITS {
+                let commit = repo.find_commit(oid)?;
+                commits.push(describe_commit(&repo, &commit)?);
+            }
+            total += 1;
+        }
+        Ok(CommitRange { commits, total })
+    })
+    .await
+    .map_err(|_| GitServiceError::Task)?
+}
+
+pub async fn is_ancestor(
+    url: &str,
+    ancestor_sha: &str,
+    descendant_sha: &str,
+) -> Result<Option<bool>, GitServiceError> {
+    let url_owned = url.to_string();
+    let ancestor_sha = ancestor_sha.to_string();
+    let descendant_sha = descendant_sha.to_string();
+    task::spawn_blocking(move || {
+        let repo = open_cache(&url_owned)?;
+        let ancestor = git2::Oid::from_str(&ancestor_sha)?;
+        let descendant = git2::Oid::from_str(&descendant_sha)?;
+        if repo.find_commit(ancestor).is_err() {
+            return Ok(None);
+        }
+        if ancestor == descendant {
+            return Ok(Some(true));
+        }
+        Ok(Some(repo.graph_descendant_of(descendant, ancestor)?))
+    })
+    .await
+    .map_err(|_| GitServiceError::Task)?
+}
+
+pub async fn describe_tag(url: &str, tag_sha: &str) -> Result<TagDetails, GitServiceError> {
+    let url_owned = url.to_string();
+    let tag_sha = tag_sha.to_string();
+    task::spawn_blocking(move || {
+        let repo = open_cache(&url_owned)?;
+        let object = repo.find_object(git2::Oid::from_str(&tag_sha)?, None)?;
+        let commit = object.peel_to_commit()?;
+        let annotation = object.as_tag().map(|tag| {
+            let tagger = tag.tagger();
+            TagAnnotation {
+                tagger: tagger
+                    .as_ref()
+                    .and_then(|tagger| tagger.name())
+                    .unwrap_or("unknown")
+                    .to_string(),
+                tagged_at: tagger.map_or(0, |tagger| tagger.when().seconds()),
+                message: strip_signature(tag.message().unwrap_or_default()),
+            }
+        });
+        Ok(TagDetails {
+            commit_sha: commit.id().to_string(),
+            annotation,
+        })
+    })
+    .await
+    .map_err(|_| GitServiceError::Task)?
+}
+
+// The tag on the closest ancestor of a commit, the way `git describe --tags` picks
+// it. Only tags already in the cache are considered and the commit's own are skipped.
+pub async fn nearest_tag(url: &str, commit_sha: &str) -> Result<Option<String>, GitServiceError> {
+    let url_owned = url.to_string();
+    let commit_sha = commit_sha.to_string();
+    task::spawn_blocking(move || {
+        let repo = open_cache(&url_owned)?;
+        let start = git2::Oid::from_str(&commit_sha)?;
+
+        let mut tagged: HashMap<git2::Oid, String> = HashMap::new();
+        for reference in repo.references_glob("refs/tags/*")? {
+            let reference = reference?;
+            let (Some(name), Ok(commit)) = (reference.name(), reference.peel_to_commit()) else {
+                continue;
+            };
+            let name = name.to_string();
+            tagged
+                .entry(commit.id())
+                .and_modify(|existing| {
+                    if name > *existing {
+                        *existing = name.clone();
+                    }
+                })
+                .or_insert(name);
+        }
+
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+        walk.push(start)?;
+        for oid in walk.take(MAX_TAG_SEARCH_DEPTH) {
+            let oid = oid?;
+            if oid == start {
+                continue;
+            }
+            if let Some(name) = tagged.remove(&oid) {
+                return Ok(Some(name));
+            }
+        }
+        Ok(None)
     })
     .await
     .map_err(|_| GitServiceError::Task)?
 }
+
+// Resolves a ref name or object id in the cache to the commit it points at.
+pub async fn peel_to_commit(url: &str, spec: &str) -> Result<String, GitServiceError> {
+    let url_owned = url.to_string();
+    let spec = spec.to_string();
+    task::spawn_blocking(move || {
+        let repo = open_cache(&url_owned)?;
+        let commit = repo.revparse_single(&spec)?.peel_to_commit()?;
+        Ok(commit.id().to_string())
+    })
+    .await
+    .map_err(|_| GitServiceError::Task)?
+}
+
+// A trial merge is only trusted while its second parent is the current head, forges
+// recompute it asynchronously after every push.
+pub async fn merge_preview(
+    url: &str,
+    merge_sha: &str,
+    head_sha: &str,
+) -> Result<Option<PullRequestDiff>, GitServiceError> {
+    let url_owned = url.to_string();
+    let merge_sha = merge_sha.to_string();
+    let head_sha = head_sha.to_string();
+    task::spawn_blocking(move || {
+        let repo = open_cache(&url_owned)?;
+        let merge = repo.find_commit(git2::Oid::from_str(&merge_sha)?)?;
+        if merge.parent_count() != 2 || merge.parent_id(1)?.to_string() != head_sha {
+            return Ok(None);
+        }
+        let base = merge.parent(0)?;
+        let stats = repo
+            .diff_tree_to_tree(Some(&base.tree()?), Some(&merge.tree()?), None)?
+            .stats()?;
+
+        let mut walk = repo.revwalk()?;
+        walk.push(merge.parent_id(1)?)?;
+        walk.hide(base.id())?;
+
+        Ok(Some(PullRequestDiff {
+            base_sha: base.id().to_string(),
+            commit_count: walk.count(),
+            files_changed: stats.files_changed(),
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+        }))
+    })
+    .await
+    .map_err(|_| GitServiceError::Task)?
+}
+
+// Signed tags carry their PGP or SSH signature at the end of the message.
+fn strip_signature(message: &str) -> String {
+    let end = message.find("-----BEGIN ").unwrap_or(message.len());
+    message[..end].trim().to_string()
+}
+
+fn transport_url(url: &str, credentials: Option<&Credentials>) -> String {
+    credentials.map_or_else(|| url.to_string(), |credentials| credentials.transport_url(url))
+}
+
+// libgit2 keeps asking for as long as the callback hands out credentials, so a
+// rejected token is only offered once. A transfer still running at the deadline
+// is cancelled from the progress callbacks, the caller has given up on it by then.
+fn remote_callbacks(
+    credentials: Option<Credentials>,
+    deadline: Instant,
+) -> git2::RemoteCallbacks<'static> {
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.transfer_progress(move |_| Instant::now() < deadline);
+    callbacks.sideband_progress(move |_| Instant::now() < deadline);
+    callbacks.certificate_check(|cert, host| Ok(check_host_key(cert, host)));
+    let Some(credentials) = credentials else {
+        return callbacks;
+    };
+
+    let mut attempted = false;
+    callbacks.credentials(move |_url, username_from_url, _allowed| {
+        if std::mem::replace(&mut attempted, true) {
+            return Err(git2::Error::from_str("credentials were rejected"));
+        }
+        match &credentials {
+            Credentials::Token { username, token } => {
+                git2::Cred::userpass_plaintext(username, token)
+            }
+            Credentials::SshKey { private_key } => git2::Cred::ssh_key_from_memory(
+                username_from_url.unwrap_or("git"),
+                None,
+                private_key,
+                None,
+            ),
+        }
+    });
+    callbacks
+}
+
+// Passing a certificate through leaves the decision to libgit2, which checks TLS
+// certificates as usual and SSH host keys against ~/.ssh/known_hosts.
+fn check_host_key(cert: &git2::cert::Cert, host: &str) -> git2::CertificateCheckStatus {
+    let fingerprint = cert
+        .as_hostkey()
+        .and_then(|hostkey| hostkey.hash_sha256())
+        .map(|hash| STANDARD_NO_PAD.encode(hash));
+    let pinned = fingerprint.is_some_and(|fingerprint| {
+        PINNED_HOST_KEYS
+            .iter()
+            .any(|(pinned_host, pinned)| *pinned_host == host && *pinned == fingerprint)
+    });
+    if pinned {
+        git2::CertificateCheckStatus::CertificateOk
+    } else {
+        git2::CertificateCheckStatus::CertificatePassthrough
+    }
+}
+
+// Stalled sockets are given up inside libgit2 itself, so a remote that stops
+// answering doesn't keep its blocking thread after the caller moved on. libgit2
+// only applies the connect timeout to HTTP(S), SSH relies on the callbacks.
+pub fn init_network_timeouts() {
+    let millis = network_timeout().as_millis().min(c_int::MAX as u128) as c_int;
+    libgit2_sys::init();
+    for option in [GIT_OPT_SET_SERVER_CONNECT_TIMEOUT, GIT_OPT_SET_SERVER_TIMEOUT] {
+        // Both options take a single int, older libgit2 versions reject them.
+        if unsafe { libgit2_sys::git_libgit2_opts(option, millis) } < 0 {
+            log::warn!("libgit2 doesn't support server timeouts, stalled remotes hold a thread");
+            return;
+        }
+    }
+}
+
+fn network_timeout() -> Duration {
+    Duration::from_secs(config::env_or("GIT_TIMEOUT_SECS", 30))
+}
+
+async fn with_network_timeout<T>(
+    task: task::JoinHandle<Result<T, GitServiceError>>,
+) -> Result<T, GitServiceError> {
+    let timeout = network_timeout();
+    match tokio::time::timeout(timeout, task).await {
+        Ok(result) => result.map_err(|_| GitServiceError::Task)?,
+        Err(_) => Err(GitServiceError::Timeout(timeout)),
+    }
+}
+
+fn cache_path(url: &str) -> PathBuf {
+    let cache_dir = env::var("GIT_CACHE_DIR").unwrap_or_else(|_| "cache".to_string());
+    PathBuf::from(cache_dir).join(format!("{:x}", Sha256::digest(url.as_bytes())))
+}
+
+// Called whenever a repository row goes away, nothing else would ever clean it up.
+pub async fn remove_cache(url: &str) {
+    let path = cache_path(url);
+    let result = task::spawn_blocking(move || match std::fs::remove_dir_all(&path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::warn!("Failed to remove the cache of {}: {:?}", url, e),
+        Err(e) => log::warn!("Failed to remove the cache of {}: {:?}", url, e),
+    }
+}
+
+fn open_cache(url: &str) -> Result<git2::Repository, git2::Error> {
+    let path = cache_path(url);
+    match git2::Repository::open_bare(&path) {
+        Ok(repo) => Ok(repo),
+        Err(_) => git2::Repository::init_bare(&path),
+    }
+}
+
+fn describe_commit(repo: &git2::Repository, commit: &git2::Commit) -> Result<Commit, git2::Error> {
+    let tree = commit.tree()?;
+    let parent_tree = match commit.parents().next() {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+    let stats = repo
+        .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?
+        .stats()?;
+
+    Ok(Commit {
+        sha: commit.id().to_string(),
+        author: commit.author().name().unwrap_or("unknown").to_string(),
+        summary: commit.summary().unwrap_or_default().to_string(),
+        files_changed: stats.files_changed(),
+        insertions: stats.insertions(),
+        deletions: stats.deletions(),
+    })
+}
@@ -1,3 +1,14 @@
+pub mod backup;
+pub mod credentials;
+pub mod delivery;
+pub mod digest;
 pub mod events;
+pub mod filters;
+pub mod forge;
 pub mod git_service;
+pub mod quiet_hours;
+pub mod releases;
+pub mod repo_url;
+pub mod scheduler;
+pub mod topics;
 pub mod updater;
@@ -0,0 +1,171 @@
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuietHoursMode {
+    #[default]
+    Hold,
+    Summarize,
+    Silent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub mode: QuietHoursMode,
+}
+
+impl QuietHoursMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuietHoursMode::Hold => "hold",
+            QuietHoursMode::Summarize => "summarize",
+            QuietHoursMode::Silent => "silent",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<Self> {
+        match value {
+            "hold" => Some(QuietHoursMode::Hold),
+            "summarize" => Some(QuietHoursMode::Summarize),
+            "silent" => Some(QuietHoursMode::Silent),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            QuietHoursMode::Hold => "Deliver afterwards",
+            QuietHoursMode::Summarize => "Summarize afterwards",
+            QuietHoursMode::Silent => "Send silently",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            QuietHoursMode::Hold => QuietHoursMode::Summarize,
+            QuietHoursMode::Summarize => QuietHoursMode::Silent,
+            QuietHoursMode::Silent => QuietHoursMode::Hold,
+        }
+    }
+}
+
+impl QuietHours {
+    pub fn from_db(start: Option<NaiveTime>, end: Option<NaiveTime>, mode: &str) -> Option<Self> {
+        Some(QuietHours {
+            start: start?,
+            end: end?,
+            mode: QuietHoursMode::from_db(mode).unwrap_or_default(),
+        })
+    }
+
+    // Returns when the current window ends, or None outside of quiet hours.
+    // Windows like 22:00-07:00 wrap around midnight.
+    pub fn active_until(&self, timezone: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let tz: Tz = timezone.parse().unwrap_or(Tz::UTC);
+        let local_now = now.with_timezone(&tz);
+        let time = local_now.time();
+        let today = local_now.date_naive();
+
+        let end_date = if self.start <= self.end {
+            if time < self.start || time >= self.end {
+                return None;
+            }
+            today
+        } else if time >= self.start {
+            today.succ_opt()?
+        } else if time < self.end {
+            today
+        } else {
+            return None;
+        };
+
+        // An end inside a DST gap doesn't exist locally, an hour later it's past the gap.
+        let local_end = end_date.and_time(self.end);
+        let end = tz.from_local_datetime(&local_end).earliest().or_else(|| {
+            tz.from_local_datetime(&(local_end + Duration::hours(1)))
+                .earliest()
+        })?;
+        Some(end.with_timezone(&Utc))
+    }
+
+    pub fn parse_window(text: &str) -> Option<(NaiveTime, NaiveTime)> {
+        let (start, end) = text.split_once('-')?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+        (start != end).then_some((start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: &str, end: &str) -> QuietHours {
+        QuietHours {
+            start: NaiveTime::parse_from_str(start, "%H:%M").unwrap(),
+            end: NaiveTime::parse_from_str(end, "%H:%M").unwrap(),
+            mode: QuietHoursMode::Hold,
+        }
+    }
+
+    fn at(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn same_day_window_includes_start_and_excludes_end() {
+        let quiet = window("09:00", "17:00");
+        assert_eq!(quiet.active_until("UTC", at("2026-05-04T08:59:00Z")), None);
+        assert_eq!(
+            quiet.active_until("UTC", at("2026-05-04T09:00:00Z")),
+            Some(at("2026-05-04T17:00:00Z"))
+        );
+        assert_eq!(quiet.active_until("UTC", at("2026-05-04T17:00:00Z")), None);
+    }
+
+    #[test]
+    fn window_wraps_around_midnight() {
+        let quiet = window("22:00", "07:00");
+        assert_eq!(
+            quiet.active_until("UTC", at("2026-05-04T23:30:00Z")),
+            Some(at("2026-05-05T07:00:00Z"))
+        );
+        assert_eq!(
+            quiet.active_until("UTC", at("2026-05-05T06:59:00Z")),
+            Some(at("2026-05-05T07:00:00Z"))
+        );
+        assert_eq!(quiet.active_until("UTC", at("2026-05-05T07:00:00Z")), None);
+        assert_eq!(quiet.active_until("UTC", at("2026-05-05T21:59:00Z")), None);
+    }
+
+    #[test]
+    fn window_follows_the_chat_timezone() {
+        let quiet = window("22:00", "07:00");
+        // 21:30 UTC is 23:30 in Berlin during summer time.
+        assert_eq!(
+            quiet.active_until("Europe/Berlin", at("2026-05-04T21:30:00Z")),
+            Some(at("2026-05-05T05:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn end_inside_a_dst_gap_moves_past_it() {
+        // Berlin skips from 02:00 to 03:00 on 2026-03-29, 02:30 never happens.
+        let quiet = window("22:00", "02:30");
+        assert_eq!(
+            quiet.active_until("Europe/Berlin", at("2026-03-28T22:00:00Z")),
+            Some(at("2026-03-29T01:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn windows_need_two_different_times() {
+        assert!(QuietHours::parse_window("22:00 - 07:00").is_some());
+        assert!(QuietHours::parse_window("07:00-07:00").is_none());
+        assert!(QuietHours::parse_window("25:00-07:00").is_none());
+    }
+}
@@ -0,0 +1,234 @@
+use crate::core::events::{GitEvent, Tag, TrackedRef};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ReleaseKind {
+    Initial,
+    Major,
+    Minor,
+    Patch,
+    PreRelease,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Release {
+    pub version: String,
+    pub previous: Option<String>,
+    pub kind: ReleaseKind,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReleaseFilter {
+    #[default]
+    All,
+    NoPreReleases,
+    StableOnly,
+    MajorMinorOnly,
+}
+
+impl ReleaseKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ReleaseKind::Initial => "first release",
+            ReleaseKind::Major => "major release",
+            ReleaseKind::Minor => "minor release",
+            ReleaseKind::Patch => "patch release",
+            ReleaseKind::PreRelease => "pre-release",
+        }
+    }
+}
+
+impl ReleaseFilter {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReleaseFilter::All => "all",
+            ReleaseFilter::NoPreReleases => "no_prerelease",
+            ReleaseFilter::StableOnly => "stable",
+            ReleaseFilter::MajorMinorOnly => "major_minor",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<Self> {
+        match value {
+            "all" => Some(ReleaseFilter::All),
+            "no_prerelease" => Some(ReleaseFilter::NoPreReleases),
+            "stable" => Some(ReleaseFilter::StableOnly),
+            "major_minor" => Some(ReleaseFilter::MajorMinorOnly),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ReleaseFilter::All => "All tags",
+            ReleaseFilter::NoPreReleases => "Ignore pre-releases",
+            ReleaseFilter::StableOnly => "Stable releases only",
+            ReleaseFilter::MajorMinorOnly => "Major/minor only",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            ReleaseFilter::All => ReleaseFilter::NoPreReleases,
+            ReleaseFilter::NoPreReleases => ReleaseFilter::StableOnly,
+            ReleaseFilter::StableOnly => ReleaseFilter::MajorMinorOnly,
+            ReleaseFilter::MajorMinorOnly => ReleaseFilter::All,
+        }
+    }
+
+    pub fn accepts(&self, tag: &Tag) -> bool {
+        let kind = tag.release.as_ref().map(|release| release.kind);
+        match self {
+            ReleaseFilter::All => true,
+            ReleaseFilter::NoPreReleases => kind != Some(ReleaseKind::PreRelease),
+            ReleaseFilter::StableOnly => kind.is_some() && kind != Some(ReleaseKind::PreRelease),
+            ReleaseFilter::MajorMinorOnly => matches!(
+                kind,
+                Some(ReleaseKind::Initial | ReleaseKind::Major | ReleaseKind::Minor)
+            ),
+        }
+    }
+}
+
+pub fn parse_version(tag_name: &str) -> Option<Version> {
+    let name = tag_name.trim_start_matches("refs/tags/");
+    let name = name
+        .strip_prefix('v')
+        .or_else(|| name.strip_prefix('V'))
+        .unwrap_or(name);
+    Version::parse(name).ok()
+}
+
+fn classify(version: &Version, previous: Option<&Version>) -> ReleaseKind {
+    if !version.pre.is_empty() {
+        return ReleaseKind::PreRelease;
+    }
+    match previous {
+        None => ReleaseKind::Initial,
+        Some(prev) if prev.major != version.major => ReleaseKind::Major,
+        Some(prev) if prev.minor != version.minor => ReleaseKind::Minor,
+        Some(_) => ReleaseKind::Patch,
+    }
+}
+
+// The previous release is the highest stable version below the new one, so a
+// backported 1.3.5 is compared against 1.3.4 rather than the latest 1.5.0.
+pub fn annotate_releases(events: &mut [GitEvent], db_refs: &HashMap<String, String>) {
+    let mut known: Vec<(Version, String)> = db_refs
+        .keys()
+        .filter(|name| matches!(TrackedRef::parse(name), Some(TrackedRef::Tag(_))))
+        .filter_map(|name| parse_version(name).map(|version| (version, name.clone())))
+        .filter(|(version, _)| version.pre.is_empty())
+        .collect();
+
+    let mut new_tags: Vec<(Version, &mut Tag)> = events
+        .iter_mut()
+        .filter_map(|event| match event {
+            GitEvent::NewTag(tag) => parse_version(&tag.name).map(|version| (version, tag)),
+            _ => None,
+        })
+        .collect();
+    new_tags.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (version, tag) in new_tags {
+        let previous = known
+            .iter()
+            .filter(|(known, _)| *known < version)
+            .max_by(|a, b| a.0.cmp(&b.0));
+        tag.release = Some(Release {
+            version: version.to_string(),
+            previous: previous.map(|(prev, _)| prev.to_string()),
+            kind: classify(&version, previous.map(|(prev, _)| prev)),
+        });
+        tag.previous_tag = previous.map(|(_, name)| name.clone());
+        if version.pre.is_empty() {
+            known.push((version, tag.name.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_tag(name: &str) -> GitEvent {
+        GitEvent::NewTag(Tag {
+            name: format!("refs/tags/{}", name),
+            sha: "0".repeat(40),
+            ..Default::default()
+        })
+    }
+
+    fn known_tags(names: &[&str]) -> HashMap<String, String> {
+        names
+            .iter()
+            .map(|name| (format!("refs/tags/{}", name), "0".repeat(40)))
+            .collect()
+    }
+
+    fn annotated(event: &GitEvent) -> (Option<Release>, Option<String>) {
+        match event {
+            GitEvent::NewTag(tag) => (tag.release.clone(), tag.previous_tag.clone()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parses_versions_with_and_without_prefix() {
+        assert_eq!(parse_version("refs/tags/v1.2.3"), Version::parse("1.2.3").ok());
+        assert_eq!(parse_version("V2.0.0-rc.1"), Version::parse("2.0.0-rc.1").ok());
+        assert_eq!(parse_version("refs/tags/nightly"), None);
+    }
+
+    #[test]
+    fn classifies_against_the_highest_lower_stable_version() {
+        let mut events = vec![new_tag("v1.3.5"), new_tag("v2.0.0"), new_tag("v1.6.0")];
+        annotate_releases(&mut events, &known_tags(&["v1.3.4", "v1.5.0", "v1.6.0-beta.1"]));
+
+        let (release, previous_tag) = annotated(&events[0]);
+        let release = release.unwrap();
+        assert_eq!(release.kind, ReleaseKind::Patch);
+        assert_eq!(release.previous.as_deref(), Some("1.3.4"));
+        assert_eq!(previous_tag.as_deref(), Some("refs/tags/v1.3.4"));
+
+        // Tags of the same poll are compared against each other in version order.
+        let (release, _) = annotated(&events[1]);
+        assert_eq!(release.unwrap().kind, ReleaseKind::Major);
+        let (release, previous_tag) = annotated(&events[2]);
+        assert_eq!(release.unwrap().kind, ReleaseKind::Minor);
+        assert_eq!(previous_tag.as_deref(), Some("refs/tags/v1.5.0"));
+    }
+
+    #[test]
+    fn first_and_pre_releases() {
+        let mut events = vec![new_tag("v0.1.0"), new_tag("v0.2.0-alpha"), new_tag("latest")];
+        annotate_releases(&mut events, &HashMap::new());
+
+        let (release, previous_tag) = annotated(&events[0]);
+        assert_eq!(release.unwrap().kind, ReleaseKind::Initial);
+        assert_eq!(previous_tag, None);
+        let (release, previous_tag) = annotated(&events[1]);
+        assert_eq!(release.unwrap().kind, ReleaseKind::PreRelease);
+        assert_eq!(previous_tag.as_deref(), Some("refs/tags/v0.1.0"));
+        assert_eq!(annotated(&events[2]), (None, None));
+    }
+
+    #[test]
+    fn release_filters() {
+        let tag = |kind| Tag {
+            release: Some(Release {
+                version: String::new(),
+                previous: None,
+                kind,
+            }),
+            ..Default::default()
+        };
+        assert!(ReleaseFilter::All.accepts(&Tag::default()));
+        assert!(!ReleaseFilter::StableOnly.accepts(&Tag::default()));
+        assert!(!ReleaseFilter::NoPreReleases.accepts(&tag(ReleaseKind::PreRelease)));
+        assert!(ReleaseFilter::MajorMinorOnly.accepts(&tag(ReleaseKind::Minor)));
+        assert!(!ReleaseFilter::MajorMinorOnly.accepts(&tag(ReleaseKind::Patch)));
+    }
+}
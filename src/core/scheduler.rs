@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+const BASE_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollOutcome {
+    Changed,
+    Unchanged,
+    Failed,
+}
+
+// Repositories that changed recently are polled every minute; the interval grows
+// as they stay quiet. Failing repositories back off exponentially instead.
+pub fn next_delay(outcome: PollOutcome, idle: Duration, consecutive_failures: u32) -> Duration {
+    match outcome {
+        PollOutcome::Changed => BASE_INTERVAL,
+        PollOutcome::Unchanged => {
+            let idle_hours = idle.as_secs() / 3600;
+            let minutes = match idle_hours {
+                0 => 1,
+                1..=23 => 2,
+                24..=167 => 5,
+                168..=719 => 15,
+                _ => 30,
+            };
+            Duration::from_secs(minutes * 60)
+        }
+        PollOutcome::Failed => {
+            let factor = 2u32.saturating_pow(consecutive_failures.min(16));
+            BASE_INTERVAL.saturating_mul(factor).min(MAX_BACKOFF)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hours(hours: u64) -> Duration {
+        Duration::from_secs(hours * 3600)
+    }
+
+    #[test]
+    fn changed_repositories_are_polled_every_minute() {
+        assert_eq!(
+            next_delay(PollOutcome::Changed, hours(1000), 0),
+            BASE_INTERVAL
+        );
+    }
+
+    #[test]
+    fn idle_tiers_grow_with_quiet_time() {
+        let delay = |idle| next_delay(PollOutcome::Unchanged, idle, 0).as_secs() / 60;
+        assert_eq!(delay(Duration::from_secs(3599)), 1);
+        assert_eq!(delay(hours(1)), 2);
+        assert_eq!(delay(hours(23)), 2);
+        assert_eq!(delay(hours(24)), 5);
+        assert_eq!(delay(hours(167)), 5);
+        assert_eq!(delay(hours(168)), 15);
+        assert_eq!(delay(hours(719)), 15);
+        assert_eq!(delay(hours(720)), 30);
+    }
+
+    #[test]
+    fn failures_back_off_up_to_the_cap() {
+        let delay = |failures| next_delay(PollOutcome::Failed, Duration::ZERO, failures);
+        assert_eq!(delay(0), BASE_INTERVAL);
+        assert_eq!(delay(1), BASE_INTERVAL * 2);
+        assert_eq!(delay(3), BASE_INTERVAL * 8);
+        assert_eq!(delay(9), MAX_BACKOFF);
+        assert_eq!(delay(u32::MAX), MAX_BACKOFF);
+    }
+}
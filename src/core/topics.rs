@@ -0,0 +1,74 @@
+use crate::core::events::GitEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventCategory {
+    Branch,
+    Tag,
+    PullRequest,
+}
+
+// Forum topics a subscription posts into; category routes win over the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TopicRoutes {
+    pub default: Option<i32>,
+    pub branch: Option<i32>,
+    pub tag: Option<i32>,
+    pub pull_request: Option<i32>,
+}
+
+impl EventCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventCategory::Branch => "branch",
+            EventCategory::Tag => "tag",
+            EventCategory::PullRequest => "pr",
+        }
+    }
+
+    pub fn from_db(value: &str) -> Option<Self> {
+        match value {
+            "branch" => Some(EventCategory::Branch),
+            "tag" => Some(EventCategory::Tag),
+            "pr" => Some(EventCategory::PullRequest),
+            _ => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EventCategory::Branch => "Branches",
+            EventCategory::Tag => "Tags",
+            EventCategory::PullRequest => "Pull requests",
+        }
+    }
+
+    pub fn of(event: &GitEvent) -> Option<Self> {
+        match event {
+            GitEvent::NewBranch(_)
+            | GitEvent::BranchUpdated { .. }
+            | GitEvent::BranchForcePushed { .. }
+            | GitEvent::BranchDeleted(_) => Some(EventCategory::Branch),
+            GitEvent::NewTag(_) | GitEvent::TagDeleted(_) => Some(EventCategory::Tag),
+            GitEvent::NewPullRequest(_)
+            | GitEvent::PullRequestUpdated(_)
+            | GitEvent::PullRequestClosed(_) => Some(EventCategory::PullRequest),
+            GitEvent::NoChanges => None,
+        }
+    }
+}
+
+impl TopicRoutes {
+    pub fn get(&self, category: EventCategory) -> Option<i32> {
+        match category {
+            EventCategory::Branch => self.branch,
+            EventCategory::Tag => self.tag,
+            EventCategory::PullRequest => self.pull_request,
+        }
+    }
+
+    pub fn thread_for(&self, event: &GitEvent) -> Option<i32> {
+        EventCategory::of(event)
+            .and_then(|category| self.get(category))
+            .or(self.default)
+    }
+}
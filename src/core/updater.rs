@@ -1,24 +1,49 @@
-use crate::core::events::{Branch, GitEvent, PullRequest, Tag};
+use crate::bot::ui::unreachable_repository_menu;
+use crate::core::credentials::Credentials;
+use crate::core::delivery::Notification;
+use crate::core::digest::{DeliveryMode, DigestEntry};
+use crate::core::quiet_hours::QuietHoursMode;
+use crate::core::events::{
+    Branch, Commit, GitEvent, PullRequest, ReviewKind, ReviewRef, Tag, TagAnnotation, TrackedRef,
+};
+use crate::core::filters::{self, CompiledFilter};
+use crate::core::forge::Forge;
+use crate::core::releases;
+use crate::core::repo_url;
+use crate::core::scheduler::{self, PollOutcome};
 use crate::core::git_service::{self, GitServiceError};
-use crate::infrastructure::db::{self, DbError, DbPool, Repository};
+use crate::infrastructure::config;
+use crate::infrastructure::db::{self, DbError, DbPool, RefChange, Repository, ScheduledRepository};
+use chrono::{DateTime, Utc};
 use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
-use teloxide::prelude::*;
-use teloxide::types::ParseMode;
+use tokio::sync::{OwnedMutexGuard, Semaphore};
+use tokio::task::JoinSet;
+use teloxide::types::ChatId;
 use teloxide::utils::markdown::escape;
-use teloxide::RequestError;
 
-pub async fn run_updater(bot: Bot, pool: DbPool) {
-    let mut update_interval = tokio::time::interval(Duration::from_secs(60));
+const UNREACHABLE_WARNING_DELAY: Duration = Duration::from_secs(3600);
+const MAX_TAG_MESSAGE_CHARS: usize = 800;
+
+static REPOSITORY_LOCKS: OnceLock<Mutex<HashMap<i32, Arc<tokio::sync::Mutex<()>>>>> =
+    OnceLock::new();
+
+pub async fn run_updater(pool: DbPool) {
+    let mut update_interval = tokio::time::interval(Duration::from_secs(15));
+    update_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
     let mut cleanup_interval = tokio::time::interval(Duration::from_secs(3600));
 
     loop {
         tokio::select! {
             _ = update_interval.tick() => {
-                log::info!("Running repository update check...");
-                if let Err(e) = check_for_updates(&bot, &pool).await {
+                log::debug!("Running repository update check...");
+                if let Err(e) = check_for_updates(&pool).await {
                     log::error!("Error during repository update check: {:?}", e);
                 }
+                if let Err(e) = flush_settled_updates(&pool).await {
+                    log::error!("Error while flushing held branch updates: {:?}", e);
+                }
             }
             _ = cleanup_interval.tick() => {
                 log::info!("Running database cleanup...");
@@ -33,117 +58,613 @@ pub async fn run_updater(bot: Bot, pool: DbPool) {
 pub async fn cleanup_database(
     pool: &DbPool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let repos_affected = db::remove_orphan_repositories(pool).await?;
-    if repos_affected > 0 {
-        log::info!("Removed {} orphan repositories.", repos_affected);
+    let removed_repos = db::remove_orphan_repositories(pool).await?;
+    if !removed_repos.is_empty() {
+        log::info!("Removed {} orphan repositories.", removed_repos.len());
+    }
+    for url in &removed_repos {
+        git_service::remove_cache(url).await;
     }
 
-    let users_affected = db::remove_orphan_users(pool).await?;
-    if users_affected > 0 {
-        log::info!("Removed {} orphan users.", users_affected);
+    let chats_affected = db::remove_orphan_chats(pool).await?;
+    if chats_affected > 0 {
+        log::info!("Removed {} orphan chats.", chats_affected);
     }
 
     Ok(())
 }
 
+// Rows added before URLs were canonicalized may point at the same repository under
+// different spellings. Safe to run on every start.
+pub async fn canonicalize_repository_urls(
+    pool: &DbPool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    for repo in db::get_all_repositories(pool).await? {
+        let canonical = repo_url::canonicalize(&repo.url);
+        if canonical == repo.url {
+            continue;
+        }
+
+        match db::get_repository_id_by_url(pool, &canonical).await? {
+            Some(existing_id) if existing_id != repo.id => {
+                db::merge_repository(pool, repo.id, existing_id).await?;
+                log::info!("Merged {} into {}.", repo.url, canonical);
+            }
+            _ => {
+                db::set_repository_url(pool, repo.id, &canonical).await?;
+                log::info!("Renamed {} to {}.", repo.url, canonical);
+            }
+        }
+        // Caches are keyed by URL, the old spelling's is never opened again.
+        git_service::remove_cache(&repo.url).await;
+    }
+    Ok(())
+}
+
 pub async fn check_for_updates(
-    bot: &Bot,
     pool: &DbPool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let repos = db::get_all_repositories(pool).await?;
+    let webhook_stale_mins = config::env_or("WEBHOOK_STALE_MINS", 60);
+    let repos = db::get_due_repositories(pool, webhook_stale_mins).await?;
+
+    let workers = Arc::new(Semaphore::new(config::env_or("POLL_WORKERS", 8)));
+    let per_host_limit = config::env_or("POLL_PER_HOST_LIMIT", 2);
+    let mut host_limits: HashMap<String, Arc<Semaphore>> = HashMap::new();
+    let mut tasks = JoinSet::new();
+
+    for scheduled in repos {
+        let host_limit = host_limits
+            .entry(repository_host(&scheduled.repo.url))
+            .or_insert_with(|| Arc::new(Semaphore::new(per_host_limit)))
+            .clone();
+        let workers = workers.clone();
+        let pool = pool.clone();
+
+        tasks.spawn(async move {
+            let _host_permit = host_limit.acquire_owned().await;
+            let _worker_permit = workers.acquire_owned().await;
+            let _repo_lock = lock_repository(scheduled.repo.id).await;
+            let outcome = match check_repository(&pool, &scheduled).await {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    log::error!("Error while checking {}: {:?}", scheduled.repo.url, e);
+                    PollOutcome::Failed
+                }
+            };
+            if let Err(e) = reschedule_repository(&pool, &scheduled, outcome).await {
+                log::error!("Failed to reschedule {}: {:?}", scheduled.repo.url, e);
+            }
+        });
+    }
+
+    while tasks.join_next().await.is_some() {}
+    Ok(())
+}
+
+async fn check_repository(
+    pool: &DbPool,
+    scheduled: &ScheduledRepository,
+) -> Result<PollOutcome, Box<dyn std::error::Error + Send + Sync>> {
+    let repo = &scheduled.repo;
+    log::debug!("Checking repo: {}", repo.url);
+    let mut credentials = load_credentials(pool, repo.id).await?;
+    let recheck_secs = config::env_or("CREDENTIALS_RECHECK_HOURS", 24i64) * 3600;
+    if scheduled.requires_credentials
+        && scheduled.credentials_age_secs.is_none_or(|age| age >= recheck_secs)
+    {
+        verify_credentials(pool, repo, &mut credentials).await?;
+    }
+    let remote_refs = match list_remote_refs(pool, repo.id, &repo.url, &credentials).await {
+        Ok(refs) => refs,
+        Err(e) => {
+            log::error!("Failed to ls-remote for {}: {:?}", repo.url, e);
+            if is_permanent_failure(&e) {
+                handle_permanent_failure(pool, scheduled).await?;
+            }
+            return Ok(PollOutcome::Failed);
+        }
+    };
+
+    let db_refs = db::get_repository_refs(pool, repo.id).await?;
+    let deleted_refs = detect_deleted_refs(&remote_refs, &db_refs);
+    let mut events = detect_events(&remote_refs, &db_refs);
+    events.extend(detect_deletion_events(&deleted_refs, &remote_refs, &db_refs));
+    let outcome = if events.is_empty() {
+        PollOutcome::Unchanged
+    } else {
+        PollOutcome::Changed
+    };
+    process_events(pool, repo, events, &db_refs, &remote_refs, true).await?;
+
+    // Refs that changed without anything to announce, a moved trial merge or a
+    // leftover entry, are brought in line in one go once the events are committed.
+    let mut housekeeping: Vec<RefChange> = remote_refs
+        .iter()
+        .filter(|(ref_name, sha)| {
+            TrackedRef::parse(ref_name).is_some_and(|tracked| tracked.is_review_merge())
+                && db_refs.get(*ref_name).is_some_and(|old_sha| old_sha != *sha)
+        })
+        .map(|(ref_name, sha)| RefChange::Update {
+            ref_name: ref_name.clone(),
+            sha: sha.clone(),
+        })
+        .collect();
+    if !deleted_refs.is_empty() {
+        log::info!("Detected {} deleted refs for {}", deleted_refs.len(), repo.url);
+        housekeeping.extend(
+            deleted_refs
+                .into_iter()
+                .map(|ref_name| RefChange::Delete { ref_name }),
+        );
+    }
+    if !housekeeping.is_empty() {
+        db::commit_event(pool, repo.id, &housekeeping, &[], &[]).await?;
+    }
+    Ok(outcome)
+}
+
+async fn reschedule_repository(
+    pool: &DbPool,
+    scheduled: &ScheduledRepository,
+    outcome: PollOutcome,
+) -> Result<(), DbError> {
+    let idle = Duration::from_secs(scheduled.idle_secs.unwrap_or(0).max(0) as u64);
+    let failures = scheduled.consecutive_failures.max(0) as u32;
+
+    if outcome == PollOutcome::Failed {
+        let delay = scheduler::next_delay(outcome, idle, failures + 1);
+        log::debug!("Backing off {} for {:?}", scheduled.repo.url, delay);
+        db::record_poll_failure(pool, scheduled.repo.id, delay.as_secs()).await
+    } else {
+        let delay = scheduler::next_delay(outcome, idle, 0);
+        let changed = outcome == PollOutcome::Changed;
+        db::record_poll_success(pool, scheduled.repo.id, changed, delay.as_secs()).await
+    }
+}
+
+struct SubscriptionCredentials {
+    chat_id: i64,
+    credentials: Credentials,
+    valid: bool,
+}
+
+async fn load_credentials(
+    pool: &DbPool,
+    repo_id: i32,
+) -> Result<Vec<SubscriptionCredentials>, DbError> {
+    let stored = db::get_repository_credentials(pool, repo_id).await?;
+    Ok(stored
+        .into_iter()
+        .filter_map(|stored| match Credentials::decrypt(&stored.sealed) {
+            Ok(credentials) => Some(SubscriptionCredentials {
+                chat_id: stored.chat_id,
+                credentials,
+                valid: stored.valid,
+            }),
+            Err(e) => {
+                log::warn!(
+                    "Skipping credentials of {} for repository {}: {}",
+                    stored.chat_id,
+                    repo_id,
+                    e
+                );
+                None
+            }
+        })
+        .collect())
+}
+
+fn working_credentials(credentials: &[SubscriptionCredentials]) -> Option<&Credentials> {
+    credentials
+        .iter()
+        .find(|candidate| candidate.valid)
+        .map(|candidate| &candidate.credentials)
+}
+
+async fn load_ref_filters(
+    pool: &DbPool,
+    repo_id: i32,
+) -> Result<HashMap<ChatId, Vec<CompiledFilter>>, DbError> {
+    Ok(db::get_repository_filters(pool, repo_id)
+        .await?
+        .into_iter()
+        .map(|(chat_id, chat_filters)| (chat_id, filters::compile_filters(&chat_filters)))
+        .collect())
+}
+
+async fn list_remote_refs(
+    pool: &DbPool,
+    repo_id: i32,
+    url: &str,
+    credentials: &[SubscriptionCredentials],
+) -> Result<HashMap<String, String>, GitServiceError> {
+    if !credentials.is_empty() {
+        return list_remote_refs_with(pool, repo_id, url, credentials).await;
+    }
+    let result = git_service::ls_remote(url, None).await;
+    if !result.as_ref().is_err_and(is_permanent_failure) {
+        return result;
+    }
+    // Nothing else flips a repository back to private, and without its credentials
+    // the permanent failure would unsubscribe the chats that supplied them.
+    let stored = match db::reinstate_credentials_requirement(pool, repo_id).await {
+        Ok(true) => load_credentials(pool, repo_id).await,
+        Ok(false) => return result,
+        Err(e) => Err(e),
+    };
+    match stored {
+        Ok(stored) => {
+            log::info!("{} refuses anonymous access again, using its stored credentials", url);
+            list_remote_refs_with(pool, repo_id, url, &stored).await
+        }
+        Err(e) => {
+            log::error!("Failed to restore the credentials of {}: {:?}", url, e);
+            result
+        }
+    }
+}
 
-    for repo in &repos {
-        log::debug!("Checking repo: {}", repo.url);
-        let remote_refs = match git_service::ls_remote(&repo.url).await {
-            Ok(refs) => refs,
+// A private repository is reachable as long as one subscriber's credentials still
+// work. Credentials the forge turns away stop counting until they're verified again.
+async fn list_remote_refs_with(
+    pool: &DbPool,
+    repo_id: i32,
+    url: &str,
+    credentials: &[SubscriptionCredentials],
+) -> Result<HashMap<String, String>, GitServiceError> {
+    let mut last_error = None;
+    for candidate in credentials.iter().filter(|candidate| candidate.valid) {
+        match git_service::ls_remote(url, Some(&candidate.credentials)).await {
+            Ok(refs) => return Ok(refs),
             Err(e) => {
-                if let GitServiceError::Git(git_err) = &e {
-                    if git_err.class() == git2::ErrorClass::Http
-                        && (git_err.code() == git2::ErrorCode::Auth
-                            || git_err.code() == git2::ErrorCode::NotFound)
+                log::debug!("Credentials of {} rejected by {}: {:?}", candidate.chat_id, url, e);
+                if is_permanent_failure(&e) {
+                    if let Err(e) =
+                        db::set_credentials_valid(pool, candidate.chat_id, repo_id, false).await
                     {
-                        log::warn!(
-                            "Repository {} is inaccessible (private or deleted). Removing.",
-                            repo.url
-                        );
-                        handle_inaccessible_repository(bot, pool, &repo).await?;
+                        log::error!("Failed to record rejected credentials for {}: {:?}", url, e);
                     }
                 }
-                log::error!("Failed to ls-remote for {}: {:?}", repo.url, e);
-                continue;
+                last_error = Some(e);
             }
+        }
+    }
+    match last_error {
+        Some(e) => Err(e),
+        None => git_service::ls_remote(url, None).await,
+    }
+}
+
+// Every set of credentials is tried again now and then, so a renewed token counts
+// again and a repository that went public stops requiring any.
+async fn verify_credentials(
+    pool: &DbPool,
+    repo: &Repository,
+    credentials: &mut Vec<SubscriptionCredentials>,
+) -> Result<(), DbError> {
+    if git_service::ls_remote(&repo.url, None).await.is_ok() {
+        log::info!("{} is public now, its credentials are no longer needed", repo.url);
+        db::record_credentials_check(pool, repo.id, false).await?;
+        credentials.clear();
+        return Ok(());
+    }
+
+    for candidate in credentials.iter_mut() {
+        let valid = match git_service::ls_remote(&repo.url, Some(&candidate.credentials)).await {
+            Ok(_) => true,
+            Err(e) if is_permanent_failure(&e) => false,
+            Err(_) => candidate.valid,
         };
+        if valid != candidate.valid {
+            log::info!(
+                "Credentials of {} for {} are {}",
+                candidate.chat_id,
+                repo.url,
+                if valid { "working again" } else { "no longer accepted" }
+            );
+            db::set_credentials_valid(pool, candidate.chat_id, repo.id, valid).await?;
+            candidate.valid = valid;
+        }
+    }
+    db::record_credentials_check(pool, repo.id, true).await
+}
 
-        let db_refs = db::get_repository_refs(pool, repo.id).await?;
-        let events = detect_events(&remote_refs, &db_refs);
+// A poll, a settling update and every subscriber's webhook for the same push all
+// compare against the stored refs, only one of them may do so at a time or each
+// would announce the change.
+async fn lock_repository(repo_id: i32) -> OwnedMutexGuard<()> {
+    let lock = REPOSITORY_LOCKS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry(repo_id)
+        .or_default()
+        .clone();
+    lock.lock_owned().await
+}
+
+fn repository_host(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split('/').next().unwrap_or(rest);
+    let host_port = authority.rsplit('@').next().unwrap_or(authority);
+    host_port.split(':').next().unwrap_or(host_port).to_lowercase()
+}
+
+pub async fn process_webhook_events(
+    pool: &DbPool,
+    repo: &Repository,
+    events: Vec<GitEvent>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let _repo_lock = lock_repository(repo.id).await;
+    let credentials = load_credentials(pool, repo.id).await?;
+    let remote_refs = list_remote_refs(pool, repo.id, &repo.url, &credentials).await?;
+    let db_refs = db::get_repository_refs(pool, repo.id).await?;
+    let events = events
+        .into_iter()
+        .filter_map(|event| confirm_with_remote(event, &remote_refs, &db_refs))
+        .filter(|event| !is_already_applied(event, &db_refs))
+        .collect();
+    process_events(pool, repo, events, &db_refs, &remote_refs, false).await
+}
+
+// Polls hold branch updates so a burst of pushes is reported once. A webhook already
+// arrives per push and is expected to be reported right away, so it never waits.
+async fn process_events(
+    pool: &DbPool,
+    repo: &Repository,
+    mut events: Vec<GitEvent>,
+    db_refs: &HashMap<String, String>,
+    remote_refs: &HashMap<String, String>,
+    hold_updates: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if events.is_empty() {
+        return Ok(());
+    }
 
-        if !events.is_empty() {
-            for event in &events {
-                log::info!("Update detected for {}: {:?}", repo.url, event);
-                update_database_from_event(pool, repo.id, event).await?;
-                notify_subscribers(bot, pool, repo.id, &repo.url, event).await?;
+    if hold_updates && config::env_or("COALESCE_WINDOW_SECS", 180u64) > 0 {
+        let (held, rest): (Vec<_>, Vec<_>) = events
+            .into_iter()
+            .partition(|event| matches!(event, GitEvent::BranchUpdated { .. }));
+        for event in held {
+            if let GitEvent::BranchUpdated { name, old_sha, new_sha, .. } = event {
+                log::debug!("Holding update of {} in {} until it settles", name, repo.url);
+                db::hold_branch_update(pool, repo.id, &name, &old_sha, &new_sha).await?;
             }
         }
+        events = rest;
+    }
+
+    releases::annotate_releases(&mut events, db_refs);
+    let credentials = load_credentials(pool, repo.id).await?;
+    enrich_events(&repo.url, working_credentials(&credentials), &mut events).await;
+    let ref_filters = load_ref_filters(pool, repo.id).await?;
+
+    // The ref update and its outgoing notifications are committed together so a
+    // crash can neither lose a notification nor send one twice for the same change.
+    for event in &events {
+        log::info!("Update detected for {}: {:?}", repo.url, event);
+        let (notifications, digest_entries) =
+            build_notifications(pool, repo.id, &repo.url, event, &ref_filters).await?;
+        db::commit_event(
+            pool,
+            repo.id,
+            &ref_changes(event, remote_refs),
+            &notifications,
+            &digest_entries,
+        )
+        .await?;
+    }
+    Ok(())
+}
 
-        let deleted_refs = detect_deleted_refs(&remote_refs, &db_refs);
-        if !deleted_refs.is_empty() {
-            log::info!("Detected {} deleted refs for {}", deleted_refs.len(), repo.url);
-            for ref_name in deleted_refs {
-                db::delete_ref(pool, repo.id, &ref_name).await?;
+// Consecutive pushes to a branch are reported as one update spanning the first
+// old SHA to the last new SHA once the branch has been quiet for the settle window.
+pub async fn flush_settled_updates(
+    pool: &DbPool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let settle_secs = config::env_or("COALESCE_WINDOW_SECS", 180);
+    let max_hold_secs = config::env_or("COALESCE_MAX_HOLD_SECS", 1800);
+
+    for pending in db::get_settled_branch_updates(pool, settle_secs, max_hold_secs).await? {
+        let repo = &pending.repo;
+        let _repo_lock = lock_repository(repo.id).await;
+        let settled = RefChange::Settled {
+            ref_name: pending.ref_name.clone(),
+            sha: pending.new_sha.clone(),
+        };
+
+        let db_refs = db::get_repository_refs(pool, repo.id).await?;
+        let deleted = !db_refs.contains_key(&pending.ref_name);
+        if deleted || pending.old_sha == pending.new_sha {
+            if deleted {
+                log::info!(
+                    "Dropping held update of {} in {} ({} → {}), the branch was deleted before it settled",
+                    pending.ref_name,
+                    repo.url,
+                    pending.old_sha,
+                    pending.new_sha
+                );
             }
+            db::commit_event(pool, repo.id, &[settled], &[], &[]).await?;
+            continue;
+        }
+
+        let mut events = vec![GitEvent::BranchUpdated {
+            name: pending.ref_name.clone(),
+            old_sha: pending.old_sha.clone(),
+            new_sha: pending.new_sha.clone(),
+            commits: Vec::new(),
+            commit_count: 0,
+            history_unknown: false,
+        }];
+        let credentials = load_credentials(pool, repo.id).await?;
+        enrich_events(&repo.url, working_credentials(&credentials), &mut events).await;
+        let ref_filters = load_ref_filters(pool, repo.id).await?;
+
+        for event in &events {
+            log::info!("Update settled for {}: {:?}", repo.url, event);
+            let (notifications, digest_entries) =
+                build_notifications(pool, repo.id, &repo.url, event, &ref_filters).await?;
+            db::commit_event(
+                pool,
+                repo.id,
+                std::slice::from_ref(&settled),
+                &notifications,
+                &digest_entries,
+            )
+            .await?;
         }
     }
     Ok(())
 }
 
+// A payload only says which ref to look at, the SHAs come from the remote and the
+// last announced state. Anyone holding a secret could otherwise announce made-up
+// commits to every subscriber, and a delivery that arrives late is caught up too.
+fn confirm_with_remote(
+    event: GitEvent,
+    remote_refs: &HashMap<String, String>,
+    db_refs: &HashMap<String, String>,
+) -> Option<GitEvent> {
+    let confirmed = match event {
+        GitEvent::NewBranch(Branch { name, .. }) => {
+            let sha = remote_refs.get(&name)?.clone();
+            GitEvent::NewBranch(Branch { name, sha })
+        }
+        GitEvent::NewTag(tag) => {
+            let sha = remote_refs.get(&tag.name)?.clone();
+            GitEvent::NewTag(Tag { sha, ..tag })
+        }
+        GitEvent::BranchUpdated { name, old_sha, .. }
+        | GitEvent::BranchForcePushed { name, old_sha, .. } => {
+            let new_sha = remote_refs.get(&name)?.clone();
+            GitEvent::BranchUpdated {
+                old_sha: db_refs.get(&name).cloned().unwrap_or(old_sha),
+                name,
+                new_sha,
+                commits: Vec::new(),
+                commit_count: 0,
+                history_unknown: false,
+            }
+        }
+        GitEvent::NewPullRequest(pr) | GitEvent::PullRequestUpdated(pr) => {
+            let head_ref = pr.head_ref();
+            let sha = remote_refs.get(&head_ref)?.clone();
+            let pr = PullRequest { sha, ..pr };
+            if db_refs.contains_key(&head_ref) {
+                GitEvent::PullRequestUpdated(pr)
+            } else {
+                GitEvent::NewPullRequest(pr)
+            }
+        }
+        GitEvent::BranchDeleted(Branch { ref name, .. })
+        | GitEvent::TagDeleted(Tag { ref name, .. })
+            if remote_refs.contains_key(name) =>
+        {
+            return None;
+        }
+        // Forges keep the head ref of a closed pull request around, so the remote can
+        // only rule a closure out: the review must be tracked and have no trial merge.
+        GitEvent::PullRequestClosed(ref pr) => {
+            let merge_open = pr
+                .merge_ref()
+                .is_some_and(|merge_ref| remote_refs.contains_key(&merge_ref));
+            if merge_open || !db_refs.contains_key(&pr.head_ref()) {
+                return None;
+            }
+            event
+        }
+        event => event,
+    };
+    Some(confirmed)
+}
+
+// Webhook deliveries can overlap with a poll that already picked the change up.
+fn is_already_applied(event: &GitEvent, db_refs: &HashMap<String, String>) -> bool {
+    match event {
+        GitEvent::NewBranch(Branch { name, sha }) | GitEvent::NewTag(Tag { name, sha, .. }) => {
+            db_refs.get(name) == Some(sha)
+        }
+        GitEvent::BranchUpdated { name, new_sha, .. }
+        | GitEvent::BranchForcePushed { name, new_sha, .. } => db_refs.get(name) == Some(new_sha),
+        GitEvent::NewPullRequest(pr) | GitEvent::PullRequestUpdated(pr) => {
+            db_refs.get(&pr.head_ref()) == Some(&pr.sha)
+        }
+        GitEvent::BranchDeleted(Branch { name, .. }) | GitEvent::TagDeleted(Tag { name, .. }) => {
+            !db_refs.contains_key(name)
+        }
+        GitEvent::PullRequestClosed(_) => false,
+        GitEvent::NoChanges => true,
+    }
+}
+
 fn detect_events(
     remote_refs: &HashMap<String, String>,
     db_refs: &HashMap<String, String>,
 ) -> Vec<GitEvent> {
     let mut events = Vec::new();
 
+    // Gerrit keeps every patchset of a change, only the newest one is worth announcing.
+    let mut latest_patchsets: HashMap<u64, u32> = HashMap::new();
+    for review in remote_refs.keys().filter_map(|name| ReviewRef::parse(name)) {
+        if let Some(patchset) = review.patchset {
+            let latest = latest_patchsets.entry(review.id).or_insert(patchset);
+            *latest = (*latest).max(patchset);
+        }
+    }
+    let known_changes: HashSet<u64> = db_refs
+        .keys()
+        .filter_map(|name| ReviewRef::parse(name))
+        .filter(|review| review.kind == ReviewKind::Change)
+        .map(|review| review.id)
+        .collect();
+
     for (ref_name, new_sha) in remote_refs {
-        let event = match db_refs.get(ref_name) {
-            Some(old_sha) if old_sha == new_sha => None,
-            Some(old_sha) => {
-                if ref_name.starts_with("refs/heads/") {
-                    Some(GitEvent::BranchUpdated {
-                        name: ref_name.clone(),
-                        old_sha: old_sha.clone(),
-                        new_sha: new_sha.clone(),
-                    })
-                } else if ref_name.starts_with("refs/pull/") {
-                    ref_name.split('/').nth(2).and_then(|id| id.parse().ok()).map(|pr_id| {
-                        GitEvent::PullRequestUpdated(PullRequest {
-                            id: pr_id,
-                            sha: new_sha.clone(),
-                        })
-                    })
-                } else {
-                    None
-                }
+        let Some(tracked) = TrackedRef::parse(ref_name) else {
+            continue;
+        };
+        if let TrackedRef::Review(review) = tracked {
+            let superseded = review.patchset.zip(latest_patchsets.get(&review.id)).is_some_and(
+                |(patchset, latest)| patchset < *latest,
+            );
+            if superseded {
+                continue;
             }
-            None => {
-                if ref_name.starts_with("refs/heads/") {
-                    Some(GitEvent::NewBranch(Branch {
-                        name: ref_name.clone(),
-                        sha: new_sha.clone(),
-                    }))
-                } else if ref_name.starts_with("refs/tags/") {
-                    Some(GitEvent::NewTag(Tag {
-                        name: ref_name.clone(),
-                        sha: new_sha.clone(),
-                    }))
-                } else if ref_name.starts_with("refs/pull/") {
-                    ref_name.split('/').nth(2).and_then(|id| id.parse().ok()).map(|pr_id| {
-                        GitEvent::NewPullRequest(PullRequest {
-                            id: pr_id,
-                            sha: new_sha.clone(),
-                        })
-                    })
+            if review.is_merge {
+                events.extend(detect_merge_restored(&review, new_sha, remote_refs, db_refs));
+                continue;
+            }
+        }
+        let event = match (tracked, db_refs.get(ref_name)) {
+            (_, Some(old_sha)) if old_sha == new_sha => None,
+            (TrackedRef::Branch(_), Some(old_sha)) => Some(GitEvent::BranchUpdated {
+                name: ref_name.clone(),
+                old_sha: old_sha.clone(),
+                new_sha: new_sha.clone(),
+                commits: Vec::new(),
+                commit_count: 0,
+                history_unknown: false,
+            }),
+            (TrackedRef::Branch(_), None) => Some(GitEvent::NewBranch(Branch {
+                name: ref_name.clone(),
+                sha: new_sha.clone(),
+            })),
+            (TrackedRef::Tag(_), Some(_)) => None,
+            (TrackedRef::Tag(_), None) => Some(GitEvent::NewTag(Tag {
+                name: ref_name.clone(),
+                sha: new_sha.clone(),
+                ..Default::default()
+            })),
+            (TrackedRef::Review(review), old_sha) => {
+                let mut pr = review.to_pull_request(new_sha);
+                attach_merge_state(&mut pr, remote_refs, db_refs);
+                let known = old_sha.is_some()
+                    || (review.kind == ReviewKind::Change && known_changes.contains(&review.id));
+                Some(if known {
+                    GitEvent::PullRequestUpdated(pr)
                 } else {
-                    None
-                }
+                    GitEvent::NewPullRequest(pr)
+                })
             }
         };
         if let Some(event) = event {
@@ -153,6 +674,196 @@ fn detect_events(
     events
 }
 
+async fn enrich_tag(repo_url: &str, tag: &mut Tag) {
+    let details = match git_service::describe_tag(repo_url, &tag.sha).await {
+        Ok(details) => details,
+        Err(e) => {
+            log::warn!("Failed to read tag {} in {}: {:?}", tag.name, repo_url, e);
+            return;
+        }
+    };
+    tag.annotation = details.annotation;
+
+    // Without a version to order by, the previous release is whatever tag the new
+    // one's history reaches first.
+    if tag.previous_tag.is_none() && tag.release.is_none() {
+        match git_service::nearest_tag(repo_url, &details.commit_sha).await {
+            Ok(previous_tag) => tag.previous_tag = previous_tag,
+            Err(e) => {
+                log::warn!("Failed to find the tag before {} in {}: {:?}", tag.name, repo_url, e);
+            }
+        }
+    }
+
+    if let Some(previous_tag) = &tag.previous_tag {
+        let range = match git_service::peel_to_commit(repo_url, previous_tag).await {
+            Ok(previous_sha) => {
+                git_service::commit_range(repo_url, &previous_sha, &details.commit_sha).await
+            }
+            Err(e) => Err(e),
+        };
+        match range {
+            Ok(range) => {
+                tag.commits = range.commits;
+                tag.commit_count = range.total;
+            }
+            Err(e) => {
+                log::warn!("Failed to list commits since {} in {}: {:?}", previous_tag, repo_url, e);
+            }
+        }
+    }
+    tag.commit_sha = Some(details.commit_sha);
+}
+
+// A trial merge showing up again for an unchanged head means the base moved and
+// the conflict is gone. A moved head is reported by its own update event.
+fn detect_merge_restored(
+    review: &ReviewRef,
+    merge_sha: &str,
+    remote_refs: &HashMap<String, String>,
+    db_refs: &HashMap<String, String>,
+) -> Option<GitEvent> {
+    let mut pr = review.to_pull_request(merge_sha);
+    if db_refs.contains_key(&pr.merge_ref()?) {
+        return None;
+    }
+    let head_sha = db_refs.get(&pr.head_ref())?;
+    if remote_refs.get(&pr.head_ref()) != Some(head_sha) {
+        return None;
+    }
+    pr.sha = head_sha.clone();
+    pr.merge_sha = Some(merge_sha.to_string());
+    pr.mergeable = Some(true);
+    Some(GitEvent::PullRequestUpdated(pr))
+}
+
+// Forges drop the trial merge ref of a pull request whose head no longer merges cleanly.
+fn attach_merge_state(
+    pr: &mut PullRequest,
+    remote_refs: &HashMap<String, String>,
+    db_refs: &HashMap<String, String>,
+) {
+    let Some(merge_ref) = pr.merge_ref() else {
+        return;
+    };
+    pr.merge_sha = remote_refs.get(&merge_ref).cloned();
+    if pr.merge_sha.is_none() && db_refs.contains_key(&merge_ref) {
+        pr.mergeable = Some(false);
+    }
+}
+
+async fn enrich_events(
+    repo_url: &str,
+    credentials: Option<&Credentials>,
+    events: &mut [GitEvent],
+) {
+    let mut refspecs: Vec<String> = events
+        .iter()
+        .flat_map(|event| match event {
+            GitEvent::BranchUpdated { name, .. } => vec![name.clone()],
+            GitEvent::NewTag(tag) if tag.release.is_none() => {
+                vec![tag.name.clone(), "refs/tags/*".to_string()]
+            }
+            GitEvent::NewTag(tag) => std::iter::once(tag.name.clone())
+                .chain(tag.previous_tag.clone())
+                .collect(),
+            GitEvent::NewPullRequest(pr) | GitEvent::PullRequestUpdated(pr) => {
+                pr.merge_sha.as_ref().and(pr.merge_ref()).into_iter().collect()
+            }
+            _ => Vec::new(),
+        })
+        .map(|ref_name| format!("+{0}:{0}", ref_name))
+        .collect();
+    refspecs.sort();
+    refspecs.dedup();
+
+    if refspecs.is_empty() {
+        return;
+    }
+
+    if let Err(e) = git_service::fetch(repo_url, credentials, refspecs).await {
+        log::warn!("Failed to fetch objects for {}: {:?}", repo_url, e);
+        for event in events.iter_mut() {
+            if let GitEvent::BranchUpdated { history_unknown, .. } = event {
+                *history_unknown = true;
+            }
+        }
+        return;
+    }
+
+    for event in events.iter_mut() {
+        if let GitEvent::NewTag(tag) = event {
+            enrich_tag(repo_url, tag).await;
+        }
+
+        if let GitEvent::NewPullRequest(pr) | GitEvent::PullRequestUpdated(pr) = event {
+            let Some(merge_sha) = &pr.merge_sha else {
+                continue;
+            };
+            match git_service::merge_preview(repo_url, merge_sha, &pr.sha).await {
+                Ok(Some(diff)) => {
+                    pr.diff = Some(diff);
+                    pr.mergeable = Some(true);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!("Failed to diff {} in {}: {:?}", pr.display_id(), repo_url, e);
+                }
+            }
+        }
+
+        if let GitEvent::BranchUpdated {
+            name,
+            old_sha,
+            new_sha,
+            commits,
+            commit_count,
+            history_unknown,
+        } = event
+        {
+            // An empty cache only holds what the fetch above brought in, which misses
+            // the previous head when it was rewritten away.
+            if let Err(e) = git_service::fetch_commit(repo_url, credentials, old_sha).await {
+                log::warn!("Failed to fetch previous head of {} in {}: {:?}", name, repo_url, e);
+            }
+
+            let fast_forward = match git_service::is_ancestor(repo_url, old_sha, new_sha).await {
+                Ok(Some(fast_forward)) => fast_forward,
+                Ok(None) => {
+                    log::info!("Previous head of {} in {} is unavailable", name, repo_url);
+                    *history_unknown = true;
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("Failed to check ancestry of {} in {}: {:?}", name, repo_url, e);
+                    true
+                }
+            };
+
+            match git_service::commit_range(repo_url, old_sha, new_sha).await {
+                Ok(range) => {
+                    *commits = range.commits;
+                    *commit_count = range.total;
+                }
+                Err(e) => {
+                    log::warn!("Failed to list commits of {} in {}: {:?}", name, repo_url, e);
+                }
+            }
+
+            if !fast_forward {
+                log::info!("Force-push detected on {} in {}", name, repo_url);
+                *event = GitEvent::BranchForcePushed {
+                    name: name.clone(),
+                    old_sha: old_sha.clone(),
+                    new_sha: new_sha.clone(),
+                    commits: std::mem::take(commits),
+                    commit_count: *commit_count,
+                };
+            }
+        }
+    }
+}
+
 fn detect_deleted_refs(
     remote_refs: &HashMap<String, String>,
     db_refs: &HashMap<String, String>,
@@ -162,33 +873,158 @@ fn detect_deleted_refs(
     db_keys.difference(&remote_keys).cloned().collect()
 }
 
-async fn update_database_from_event(
-    pool: &DbPool,
-    repo_id: i32,
-    event: &GitEvent,
-) -> Result<(), DbError> {
-    match event {
-        GitEvent::NewBranch(branch) => {
-            db::update_ref_hash(pool, repo_id, &branch.name, &branch.sha).await
+fn detect_deletion_events(
+    deleted_refs: &HashSet<String>,
+    remote_refs: &HashMap<String, String>,
+    db_refs: &HashMap<String, String>,
+) -> Vec<GitEvent> {
+    let mut events = Vec::new();
+    let mut closed_prs = HashSet::new();
+
+    for ref_name in deleted_refs {
+        let Some(sha) = db_refs.get(ref_name) else {
+            continue;
+        };
+        // Peeled tag entries were once stored as tags of their own and classify as nothing.
+        match TrackedRef::parse(ref_name) {
+            Some(TrackedRef::Branch(_)) => events.push(GitEvent::BranchDeleted(Branch {
+                name: ref_name.clone(),
+                sha: sha.clone(),
+            })),
+            Some(TrackedRef::Tag(_)) => events.push(GitEvent::TagDeleted(Tag {
+                name: ref_name.clone(),
+                sha: sha.clone(),
+                ..Default::default()
+            })),
+            Some(TrackedRef::Review(review)) if review.is_merge => {
+                // Only a vanished head means the review is gone. A vanished trial merge
+                // means the head conflicts with its base; when the head moved as well,
+                // its update event already says so.
+                let head_ref = review.to_pull_request(sha).head_ref();
+                let Some(head_sha) = db_refs.get(&head_ref) else {
+                    continue;
+                };
+                if remote_refs.get(&head_ref) == Some(head_sha) {
+                    let mut pr = review.to_pull_request(head_sha);
+                    pr.mergeable = Some(false);
+                    events.push(GitEvent::PullRequestUpdated(pr));
+                }
+            }
+            Some(TrackedRef::Review(review)) => {
+                if closed_prs.insert((review.kind, review.id)) {
+                    events.push(GitEvent::PullRequestClosed(review.to_pull_request(sha)));
+                }
+            }
+            None => {}
         }
-        GitEvent::NewTag(tag) => db::update_ref_hash(pool, repo_id, &tag.name, &tag.sha).await,
-        GitEvent::BranchUpdated { name, new_sha, .. } => {
-            db::update_ref_hash(pool, repo_id, name, new_sha).await
+    }
+    events
+}
+
+// Every ref an event accounts for, so it's committed together with its notifications.
+// A closed pull request only gives up the refs the remote no longer advertises.
+fn ref_changes(event: &GitEvent, remote_refs: &HashMap<String, String>) -> Vec<RefChange> {
+    match event {
+        GitEvent::NewBranch(Branch { name, sha })
+        | GitEvent::NewTag(Tag { name, sha, .. })
+        | GitEvent::BranchUpdated { name, new_sha: sha, .. }
+        | GitEvent::BranchForcePushed { name, new_sha: sha, .. } => vec![RefChange::Update {
+            ref_name: name.clone(),
+            sha: sha.clone(),
+        }],
+        GitEvent::NewPullRequest(pr) | GitEvent::PullRequestUpdated(pr) => {
+            let head = RefChange::Update {
+                ref_name: pr.head_ref(),
+                sha: pr.sha.clone(),
+            };
+            let merge = pr.merge_ref().map(|ref_name| match remote_refs.get(&ref_name) {
+                Some(sha) => RefChange::Update {
+                    ref_name,
+                    sha: sha.clone(),
+                },
+                None => RefChange::Delete { ref_name },
+            });
+            std::iter::once(head).chain(merge).collect()
         }
-        GitEvent::NewPullRequest(pr) => {
-            let ref_name = format!("refs/pull/{}/head", pr.id);
-            db::update_ref_hash(pool, repo_id, &ref_name, &pr.sha).await
+        GitEvent::BranchDeleted(Branch { name, .. }) | GitEvent::TagDeleted(Tag { name, .. }) => {
+            vec![RefChange::Delete {
+                ref_name: name.clone(),
+            }]
         }
-        GitEvent::PullRequestUpdated(pr) => {
-            let ref_name = format!("refs/pull/{}/head", pr.id);
-            db::update_ref_hash(pool, repo_id, &ref_name, &pr.sha).await
+        GitEvent::PullRequestClosed(pr) => std::iter::once(pr.head_ref())
+            .chain(pr.merge_ref())
+            .filter(|ref_name| !remote_refs.contains_key(ref_name))
+            .map(|ref_name| RefChange::Delete { ref_name })
+            .collect(),
+        GitEvent::NoChanges => Vec::new(),
+    }
+}
+
+fn is_permanent_failure(error: &GitServiceError) -> bool {
+    match error {
+        GitServiceError::Git(git_err) => {
+            git_err.class() == git2::ErrorClass::Http
+                && (git_err.code() == git2::ErrorCode::Auth
+                    || git_err.code() == git2::ErrorCode::NotFound)
         }
-        GitEvent::NoChanges => Ok(()),
+        _ => false,
     }
 }
 
+// An auth or not-found answer is only trusted once it has persisted for the whole
+// grace period, so a forge outage or a brief 404 doesn't wipe subscriptions.
+async fn handle_permanent_failure(
+    pool: &DbPool,
+    scheduled: &ScheduledRepository,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let repo = &scheduled.repo;
+    let grace_period = Duration::from_secs(config::env_or("REPO_GRACE_PERIOD_HOURS", 72) * 3600);
+    let failing_for = Duration::from_secs(scheduled.failing_secs.unwrap_or(0).max(0) as u64);
+
+    if failing_for >= grace_period {
+        log::warn!(
+            "Repository {} has been inaccessible for {:?}. Removing.",
+            repo.url,
+            failing_for
+        );
+        handle_inaccessible_repository(pool, repo).await?;
+    } else if !scheduled.unreachable_notified && failing_for >= UNREACHABLE_WARNING_DELAY {
+        log::warn!("Repository {} is unreachable, warning subscribers.", repo.url);
+        warn_unreachable_repository(pool, repo, grace_period - failing_for).await?;
+    }
+    Ok(())
+}
+
+async fn warn_unreachable_repository(
+    pool: &DbPool,
+    repo: &Repository,
+    remaining: Duration,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let message = format!(
+        "⚠️ Repository [{}]({}) has been unreachable for a while (it may have been deleted or made private). \
+         You will be unsubscribed in about {} hours unless it becomes accessible again. \
+         Keeping it restarts the wait for every chat subscribed to it.",
+        escape(&repo.url),
+        escape(&repo.url),
+        remaining.as_secs().div_ceil(3600)
+    );
+
+    let notifications: Vec<_> = db::get_subscribers_with_settings(pool, repo.id)
+        .await?
+        .into_iter()
+        .map(|(chat_id, subscriber)| {
+            Notification::new(chat_id, message.clone())
+                .in_thread(subscriber.topics.default)
+                .with_reply_markup(unreachable_repository_menu(repo.id))
+        })
+        .collect();
+    db::enqueue_notifications(pool, &notifications).await?;
+
+    db::set_unreachable_notified(pool, repo.id).await?;
+    Ok(())
+}
+
 async fn handle_inaccessible_repository(
-    bot: &Bot,
     pool: &DbPool,
     repo: &Repository,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -198,34 +1034,22 @@ async fn handle_inaccessible_repository(
         escape(&repo.url)
     );
 
-    let subscribers = db::get_subscribers_with_settings(pool, repo.id).await?;
-    for (chat_id, _) in subscribers {
-        if let Err(e) = bot
-            .send_message(chat_id, &message)
-            .disable_web_page_preview(true)
-            .parse_mode(ParseMode::MarkdownV2)
-            .await
-        {
-            if let RequestError::Api(teloxide::ApiError::BotBlocked) = e {
-                log::warn!("User {} has blocked the bot. Removing user.", chat_id);
-                db::remove_user(pool, chat_id.0).await?;
-            } else {
-                log::error!(
-                    "Failed to send inaccessible repo notification to {}: {:?}",
-                    chat_id,
-                    e
-                );
-            }
-        }
-    }
+    let notifications: Vec<_> = db::get_subscribers_with_settings(pool, repo.id)
+        .await?
+        .into_iter()
+        .map(|(chat_id, subscriber)| {
+            Notification::new(chat_id, message.clone()).in_thread(subscriber.topics.default)
+        })
+        .collect();
+    db::enqueue_notifications(pool, &notifications).await?;
 
     db::remove_repository(pool, repo.id).await?;
+    git_service::remove_cache(&repo.url).await;
     Ok(())
 }
 
-fn format_notification_message(repo_url: &str, event: &GitEvent) -> String {
-    let base_url = repo_url.trim_end_matches(".git");
-    let short_repo_name = base_url
+pub fn short_repo_name(base_url: &str) -> String {
+    base_url
         .split('/')
         .rev()
         .take(2)
@@ -233,7 +1057,13 @@ fn format_notification_message(repo_url: &str, event: &GitEvent) -> String {
         .into_iter()
         .rev()
         .collect::<Vec<_>>()
-        .join("/");
+        .join("/")
+}
+
+fn format_notification_message(repo_url: &str, event: &GitEvent) -> String {
+    let forge = Forge::detect(repo_url);
+    let base_url = forge.base_url();
+    let short_repo_name = short_repo_name(base_url);
 
     let rendered_event = event.render_as_notification().unwrap_or_default();
 
@@ -241,8 +1071,8 @@ fn format_notification_message(repo_url: &str, event: &GitEvent) -> String {
         GitEvent::NewBranch(branch) => {
             let short_ref = branch.name.trim_start_matches("refs/heads/");
             let commit_hash_short = &branch.sha[..7];
-            let commit_url = format!("{}/commit/{}", base_url, branch.sha);
-            let ref_url = format!("{}/tree/{}", base_url, short_ref);
+            let commit_url = forge.commit_url(&branch.sha);
+            let ref_url = forge.branch_url(short_ref);
             format!(
                 "Branch: [{}]({})\nCommit: [{}]({})",
                 escape(short_ref),
@@ -253,38 +1083,112 @@ fn format_notification_message(repo_url: &str, event: &GitEvent) -> String {
         }
         GitEvent::NewTag(tag) => {
             let short_ref = tag.name.trim_start_matches("refs/tags/");
-            let commit_hash_short = &tag.sha[..7];
-            let commit_url = format!("{}/commit/{}", base_url, tag.sha);
-            let ref_url = format!("{}/releases/tag/{}", base_url, short_ref);
-            format!(
+            let commit_sha = tag.commit_sha.as_deref().unwrap_or(&tag.sha);
+            let commit_hash_short = &commit_sha[..7];
+            let commit_url = forge.commit_url(commit_sha);
+            let ref_url = forge.tag_url(short_ref);
+            let mut details = format!(
                 "Tag: [{}]({})\nCommit: [{}]({})",
                 escape(short_ref),
                 escape(&ref_url),
                 escape(commit_hash_short),
                 escape(&commit_url)
-            )
+            );
+            if let Some(release) = &tag.release {
+                let summary = match &release.previous {
+                    Some(previous) => format!(
+                        "{} {} → {}",
+                        release.kind.describe(),
+                        previous,
+                        release.version
+                    ),
+                    None => format!("{} {}", release.kind.describe(), release.version),
+                };
+                details.push_str(&format!("\nRelease: {}", escape(&summary)));
+            }
+            if let Some(annotation) = &tag.annotation {
+                details.push_str(&format_tag_annotation(annotation));
+            }
+            if !tag.commits.is_empty() {
+                let previous = tag.previous_tag.as_deref().unwrap_or_default();
+                let title = format!("Commits since {}", previous.trim_start_matches("refs/tags/"));
+                details.push_str(&format_commit_list(&forge, &title, &tag.commits, tag.commit_count));
+            }
+            details
         }
         GitEvent::BranchUpdated {
             name,
             old_sha,
             new_sha,
+            commits,
+            commit_count,
+            history_unknown,
         } => {
             let short_ref = name.trim_start_matches("refs/heads/");
-            let compare_url = format!("{}/compare/{}...{}", base_url, old_sha, new_sha);
-            format!(
-                "Branch: [{}]({}/tree/{})\nChanges: [compare]({})",
-                escape(short_ref),
-                escape(base_url),
+            let compare_url = forge.compare_url(old_sha, new_sha);
+            let mut details = format!(
+                "Branch: [{}]({})\nChanges: [compare]({})",
                 escape(short_ref),
+                escape(&forge.branch_url(short_ref)),
                 escape(&compare_url)
-            )
+            );
+            if *history_unknown {
+                details.push_str(&format!(
+                    "\n_The previous head {} could not be fetched, so this may have been a force\\-push\\._",
+                    escape(&old_sha[..7])
+                ));
+            }
+            if !commits.is_empty() {
+                details.push_str(&format_commit_list(&forge, "Commits", commits, *commit_count));
+            }
+            details
+        }
+        GitEvent::BranchForcePushed {
+            name,
+            old_sha,
+            new_sha,
+            commits,
+            commit_count,
+        } => {
+            let short_ref = name.trim_start_matches("refs/heads/");
+            let commit_url = forge.commit_url(new_sha);
+            let mut details = format!(
+                "Branch: [{}]({})\nHistory rewritten: {} → [{}]({})\n_Commits previously on this branch may no longer be reachable\\._",
+                escape(short_ref),
+                escape(&forge.branch_url(short_ref)),
+                escape(&old_sha[..7]),
+                escape(&new_sha[..7]),
+                escape(&commit_url)
+            );
+            if !commits.is_empty() {
+                details.push_str(&format_commit_list(&forge, "Commits", commits, *commit_count));
+            }
+            details
         }
-        GitEvent::NewPullRequest(pr) => {
-            format!("Pull Request: [\\#{}](_)", pr.id)
+        GitEvent::NewPullRequest(pr) | GitEvent::PullRequestUpdated(pr) => {
+            format_review_details(&forge, pr)
         }
-        GitEvent::PullRequestUpdated(pr) => {
-            format!("Pull Request: [\\#{}](_)", pr.id)
+        GitEvent::BranchDeleted(branch) => {
+            let short_ref = branch.name.trim_start_matches("refs/heads/");
+            let commit_url = forge.commit_url(&branch.sha);
+            format!(
+                "Branch: {}\nLast commit: [{}]({})",
+                escape(short_ref),
+                escape(&branch.sha[..7]),
+                escape(&commit_url)
+            )
         }
+        GitEvent::TagDeleted(tag) => {
+            let short_ref = tag.name.trim_start_matches("refs/tags/");
+            let commit_url = forge.commit_url(&tag.sha);
+            format!(
+                "Tag: {}\nCommit: [{}]({})",
+                escape(short_ref),
+                escape(&tag.sha[..7]),
+                escape(&commit_url)
+            )
+        }
+        GitEvent::PullRequestClosed(pr) => format_review_link(&forge, pr),
         GitEvent::NoChanges => "".to_string(),
     };
 
@@ -297,23 +1201,143 @@ fn format_notification_message(repo_url: &str, event: &GitEvent) -> String {
     )
 }
 
-async fn notify_subscribers(
-    bot: &Bot,
+fn format_review_link(forge: &Forge, pr: &PullRequest) -> String {
+    let display_id = escape(&pr.display_id());
+    match forge.review_url(pr) {
+        Some(url) => format!("{}: [{}]({})", pr.kind.noun(), display_id, escape(&url)),
+        None => format!("{}: {}", pr.kind.noun(), display_id),
+    }
+}
+
+fn format_review_details(forge: &Forge, pr: &PullRequest) -> String {
+    let mut details = format_review_link(forge, pr);
+    details.push_str(&format!(
+        "\nHead: [{}]({})",
+        escape(&pr.sha[..7]),
+        escape(&forge.commit_url(&pr.sha))
+    ));
+    if let Some(diff) = &pr.diff {
+        let commits_word = if diff.commit_count == 1 { "commit" } else { "commits" };
+        let files_word = if diff.files_changed == 1 { "file" } else { "files" };
+        let diffstat = format!(
+            "{} {}, {} {}, +{} -{}",
+            diff.commit_count,
+            commits_word,
+            diff.files_changed,
+            files_word,
+            diff.insertions,
+            diff.deletions
+        );
+        details.push_str(&format!(
+            "\nChanges: [{}]({}) against base {}",
+            escape(&diffstat),
+            escape(&forge.compare_url(&diff.base_sha, &pr.sha)),
+            escape(&diff.base_sha[..7])
+        ));
+    }
+    match pr.mergeable {
+        Some(true) => details.push_str("\nMergeable: ✅ yes"),
+        Some(false) => details.push_str("\nMergeable: ⚠️ conflicts with the base branch"),
+        None => {}
+    }
+    details
+}
+
+// Long release notes are cut at a line break so the message stays readable.
+fn format_tag_annotation(annotation: &TagAnnotation) -> String {
+    let tagged_at = DateTime::from_timestamp(annotation.tagged_at, 0)
+        .map(|at| at.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default();
+    let mut text = format!(
+        "\nTagged by {} on {}",
+        escape(&annotation.tagger),
+        escape(&tagged_at)
+    );
+
+    if !annotation.message.is_empty() {
+        let mut message: String = annotation.message.chars().take(MAX_TAG_MESSAGE_CHARS).collect();
+        if message.len() < annotation.message.len() {
+            if let Some(line_end) = message.rfind('\n').filter(|end| *end > message.len() / 2) {
+                message.truncate(line_end);
+            }
+            message = format!("{}…", message.trim_end());
+        }
+        text.push_str(&format!("\n\n{}", escape(&message)));
+    }
+    text
+}
+
+fn format_commit_list(forge: &Forge, title: &str, commits: &[Commit], commit_count: usize) -> String {
+    let mut text = format!("\n\n*{} \\({}\\):*", escape(title), commit_count);
+    for commit in commits {
+        let commit_url = forge.commit_url(&commit.sha);
+        let files_word = if commit.files_changed == 1 { "file" } else { "files" };
+        let diffstat = format!(
+            "{} {}, +{} -{}",
+            commit.files_changed, files_word, commit.insertions, commit.deletions
+        );
+        text.push_str(&format!(
+            "\n• [{}]({}) {}: {}\n  _{}_",
+            escape(&commit.sha[..7]),
+            escape(&commit_url),
+            escape(&commit.author),
+            escape(&commit.summary),
+            escape(&diffstat)
+        ));
+    }
+    if commit_count > commits.len() {
+        text.push_str(&format!(
+            "\n_\\.\\.\\.and {} more commits\\._",
+            commit_count - commits.len()
+        ));
+    }
+    text
+}
+
+async fn build_notifications(
     pool: &DbPool,
     repo_id: i32,
     repo_url: &str,
     event: &GitEvent,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    ref_filters: &HashMap<ChatId, Vec<CompiledFilter>>,
+) -> Result<(Vec<Notification>, Vec<DigestEntry>), DbError> {
     let subscribers = db::get_subscribers_with_settings(pool, repo_id).await?;
     let message = format_notification_message(repo_url, event);
+    let message_without_changelog = match event {
+        GitEvent::NewTag(tag) if !tag.commits.is_empty() => {
+            let tag = Tag {
+                commits: Vec::new(),
+                commit_count: 0,
+                ..tag.clone()
+            };
+            format_notification_message(repo_url, &GitEvent::NewTag(tag))
+        }
+        _ => message.clone(),
+    };
+    let mut notifications = Vec::new();
+    let mut digest_entries = Vec::new();
+    let now = Utc::now();
 
-    for (chat_id, settings) in subscribers {
+    for (chat_id, subscriber) in subscribers {
+        // Snoozing only silences updates, warnings about the repository still go out.
+        // Updates of a private repository need the chat's own working credentials.
+        if subscriber.muted_until.is_some_and(|until| until > now) || !subscriber.authorized {
+            continue;
+        }
+        let settings = &subscriber.settings;
+        let thread_id = subscriber.topics.thread_for(event);
         let should_notify = match event {
             GitEvent::NewBranch(_) => settings.notify_on_new_branch,
-            GitEvent::NewTag(_) => settings.notify_on_new_tag,
+            GitEvent::NewTag(tag) => {
+                settings.notify_on_new_tag && settings.release_filter.accepts(tag)
+            }
             GitEvent::BranchUpdated { .. } => settings.notify_on_branch_update,
+            GitEvent::BranchForcePushed { .. } => settings.notify_on_force_push,
             GitEvent::NewPullRequest(_) => settings.notify_on_new_pr,
             GitEvent::PullRequestUpdated(_) => settings.notify_on_pr_update,
+            GitEvent::BranchDeleted(_) => settings.notify_on_branch_delete,
+            GitEvent::TagDeleted(_) => settings.notify_on_tag_delete,
+            GitEvent::PullRequestClosed(_) => settings.notify_on_pr_close,
             GitEvent::NoChanges => false,
         };
 
@@ -321,20 +1345,58 @@ async fn notify_subscribers(
             continue;
         }
 
-        if let Err(e) = bot
-            .send_message(chat_id, &message)
-            .parse_mode(ParseMode::MarkdownV2)
-            .disable_web_page_preview(true)
-            .await
+        if let (Some((ref_kind, ref_name)), Some(chat_filters)) =
+            (event.filter_target(), ref_filters.get(&chat_id))
         {
-            if let RequestError::Api(teloxide::ApiError::BotBlocked) = e {
-                log::warn!("User {} has blocked the bot. Removing user.", chat_id);
-                db::remove_user(pool, chat_id.0).await?;
-            } else {
-                log::error!("Failed to send notification to {}: {:?}", chat_id, e);
+            if !filters::ref_passes(chat_filters, ref_kind, ref_name) {
+                continue;
             }
         }
+
+        let message = if settings.include_tag_changelog {
+            &message
+        } else {
+            &message_without_changelog
+        };
+        let quiet_hours = subscriber.quiet_hours.and_then(|quiet_hours| {
+            quiet_hours
+                .active_until(&subscriber.timezone, now)
+                .map(|until| (quiet_hours.mode, until))
+        });
+        let delivery_mode = match (subscriber.delivery_mode, quiet_hours) {
+            (DeliveryMode::Immediate, None) => {
+                notifications.push(Notification::new(chat_id, message.clone()).in_thread(thread_id));
+                continue;
+            }
+            (DeliveryMode::Immediate, Some((QuietHoursMode::Silent, _))) => {
+                notifications.push(
+                    Notification::new(chat_id, message.clone())
+                        .in_thread(thread_id)
+                        .silent(),
+                );
+                continue;
+            }
+            (DeliveryMode::Immediate, Some((QuietHoursMode::Hold, until))) => {
+                notifications.push(
+                    Notification::new(chat_id, message.clone())
+                        .in_thread(thread_id)
+                        .deliver_at(until),
+                );
+                continue;
+            }
+            (DeliveryMode::Immediate, Some((QuietHoursMode::Summarize, _))) => {
+                DeliveryMode::AfterQuietHours
+            }
+            (delivery_mode, _) => delivery_mode,
+        };
+        digest_entries.push(DigestEntry {
+            chat_id,
+            repo_url: repo_url.to_string(),
+            event: event.clone(),
+            delivery_mode,
+            thread_id,
+        });
     }
 
-    Ok(())
+    Ok((notifications, digest_entries))
 }
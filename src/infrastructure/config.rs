@@ -0,0 +1,27 @@
+use std::env;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+static CREDENTIALS_KEY: OnceLock<Option<[u8; 32]>> = OnceLock::new();
+
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// The 32-byte key in CREDENTIALS_KEY, given as 64 hex characters. It is parsed once,
+// every stored token and deploy key goes through it.
+pub fn credentials_key() -> Option<&'static [u8; 32]> {
+    CREDENTIALS_KEY
+        .get_or_init(|| {
+            let key = env::var("CREDENTIALS_KEY").ok()?;
+            let key = hex::decode(key.trim()).ok().and_then(|key| key.try_into().ok());
+            if key.is_none() {
+                log::warn!("CREDENTIALS_KEY must be 64 hex characters, private repositories are disabled");
+            }
+            key
+        })
+        .as_ref()
+}
@@ -1,8 +1,15 @@
+use crate::core::delivery::Notification;
+use crate::core::digest::{DeliveryMode, DigestEntry};
+use crate::core::quiet_hours::{QuietHours, QuietHoursMode};
+use crate::core::topics::{EventCategory, TopicRoutes};
+use crate::core::filters::{FilterMode, RefFilter, RefKind};
+use crate::core::releases::ReleaseFilter;
+use chrono::{DateTime, NaiveTime, Utc};
 use sha2::{Digest, Sha256};
 use sqlx::mysql::{MySqlPool, MySqlPoolOptions};
 use std::collections::HashMap;
 use std::env;
-use teloxide::types::{ChatId, User};
+use teloxide::types::{Chat, ChatId};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -21,6 +28,51 @@ pub struct Repository {
     pub url: String,
 }
 
+#[derive(Clone, sqlx::FromRow)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub chat_id: i64,
+    pub message: String,
+    pub reply_markup: Option<String>,
+    pub message_thread_id: Option<i32>,
+    pub disable_notification: bool,
+    pub attempts: i32,
+}
+
+#[derive(Debug, Clone)]
+pub enum RefChange {
+    Update { ref_name: String, sha: String },
+    Settled { ref_name: String, sha: String },
+    Delete { ref_name: String },
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct ScheduledRepository {
+    #[sqlx(flatten)]
+    pub repo: Repository,
+    pub consecutive_failures: i32,
+    pub idle_secs: Option<i64>,
+    pub failing_secs: Option<i64>,
+    pub unreachable_notified: bool,
+    pub requires_credentials: bool,
+    pub credentials_age_secs: Option<i64>,
+}
+
+pub struct StoredCredentials {
+    pub chat_id: i64,
+    pub sealed: Vec<u8>,
+    pub valid: bool,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct PendingBranchUpdate {
+    #[sqlx(flatten)]
+    pub repo: Repository,
+    pub ref_name: String,
+    pub old_sha: String,
+    pub new_sha: String,
+}
+
 #[derive(Clone, Debug, Default, sqlx::FromRow)]
 pub struct SubscriptionSettings {
     #[sqlx(default)]
@@ -33,6 +85,60 @@ pub struct SubscriptionSettings {
     pub notify_on_new_pr: bool,
     #[sqlx(default)]
     pub notify_on_pr_update: bool,
+    #[sqlx(default)]
+    pub notify_on_force_push: bool,
+    #[sqlx(default)]
+    pub notify_on_branch_delete: bool,
+    #[sqlx(default)]
+    pub notify_on_tag_delete: bool,
+    #[sqlx(default)]
+    pub notify_on_pr_close: bool,
+    #[sqlx(default)]
+    pub include_tag_changelog: bool,
+    #[sqlx(skip)]
+    pub release_filter: ReleaseFilter,
+    #[sqlx(skip)]
+    pub delivery_mode: Option<DeliveryMode>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Subscriber {
+    pub settings: SubscriptionSettings,
+    pub delivery_mode: DeliveryMode,
+    pub quiet_hours: Option<QuietHours>,
+    pub timezone: String,
+    pub topics: TopicRoutes,
+    pub muted_until: Option<DateTime<Utc>>,
+    pub authorized: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeliveryPreferences {
+    pub delivery_mode: DeliveryMode,
+    pub digest_time: NaiveTime,
+    pub timezone: String,
+    pub quiet_hours: Option<QuietHours>,
+    pub quiet_hours_mode: QuietHoursMode,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct DigestGroup {
+    pub chat_id: i64,
+    pub delivery_mode: String,
+    pub message_thread_id: Option<i32>,
+    pub oldest_entry_at: DateTime<Utc>,
+    pub timezone: String,
+    pub digest_time: NaiveTime,
+    pub quiet_hours_start: Option<NaiveTime>,
+    pub quiet_hours_end: Option<NaiveTime>,
+    pub quiet_hours_mode: String,
+}
+
+#[derive(Clone, sqlx::FromRow)]
+pub struct StoredDigestEntry {
+    pub id: i64,
+    pub repo_url: String,
+    pub event: String,
 }
 
 pub async fn create_pool() -> Result<DbPool, DbError> {
@@ -44,11 +150,23 @@ pub async fn create_pool() -> Result<DbPool, DbError> {
     Ok(pool)
 }
 
-pub async fn ensure_user_exists(pool: &DbPool, user: &User) -> Result<(), DbError> {
+pub async fn ensure_chat_exists(pool: &DbPool, chat: &Chat) -> Result<(), DbError> {
+    let kind = if chat.is_channel() {
+        "channel"
+    } else if chat.is_supergroup() {
+        "supergroup"
+    } else if chat.is_group() {
+        "group"
+    } else {
+        "private"
+    };
     sqlx::query!(
-        "INSERT IGNORE INTO users (id, username) VALUES (?, ?)",
-        user.id.0,
-        user.username
+        "INSERT INTO chats (id, kind, title, username) VALUES (?, ?, ?, ?)
+         ON DUPLICATE KEY UPDATE kind = VALUES(kind), title = VALUES(title), username = VALUES(username)",
+        chat.id.0,
+        kind,
+        chat.title(),
+        chat.username()
     )
     .execute(pool)
     .await?;
@@ -57,10 +175,11 @@ pub async fn ensure_user_exists(pool: &DbPool, user: &User) -> Result<(), DbErro
 
 pub async fn add_repository_subscription(
     pool: &DbPool,
-    user: &User,
+    chat: &Chat,
+    thread_id: Option<i32>,
     repo_url: &str,
-) -> Result<(), DbError> {
-    ensure_user_exists(pool, user).await?;
+) -> Result<i32, DbError> {
+    ensure_chat_exists(pool, chat).await?;
 
     let mut tx = pool.begin().await?;
 
@@ -85,25 +204,38 @@ pub async fn add_repository_subscription(
     };
 
     sqlx::query!(
-        "INSERT IGNORE INTO subscriptions (user_id, repository_id) VALUES (?, ?)",
-        user.id.0,
-        repo_id
+        "INSERT INTO subscriptions (chat_id, repository_id, message_thread_id) VALUES (?, ?, ?)
+         ON DUPLICATE KEY UPDATE message_thread_id = VALUES(message_thread_id)",
+        chat.id.0,
+        repo_id,
+        thread_id
     )
     .execute(&mut *tx)
     .await?;
 
     tx.commit().await?;
-    Ok(())
+    Ok(repo_id)
+}
+
+pub async fn is_subscribed(pool: &DbPool, chat_id: i64, repo_id: i32) -> Result<bool, DbError> {
+    let record = sqlx::query!(
+        "SELECT 1 AS found FROM subscriptions WHERE chat_id = ? AND repository_id = ?",
+        chat_id,
+        repo_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(record.is_some())
 }
 
 pub async fn remove_repository_subscription(
     pool: &DbPool,
-    user_id: i64,
+    chat_id: i64,
     repo_id: i32,
 ) -> Result<(), DbError> {
     sqlx::query!(
-        "DELETE FROM subscriptions WHERE user_id = ? AND repository_id = ?",
-        user_id,
+        "DELETE FROM subscriptions WHERE chat_id = ? AND repository_id = ?",
+        chat_id,
         repo_id
     )
     .execute(pool)
@@ -111,16 +243,35 @@ pub async fn remove_repository_subscription(
     Ok(())
 }
 
-pub async fn remove_orphan_repositories(pool: &DbPool) -> Result<u64, DbError> {
-    let result = sqlx::query("DELETE FROM repositories WHERE id NOT IN (SELECT DISTINCT repository_id FROM subscriptions)")
+// Returns the URLs of the removed repositories so their caches can go as well.
+pub async fn remove_orphan_repositories(pool: &DbPool) -> Result<Vec<String>, DbError> {
+    let orphans: Vec<(i32, String)> = sqlx::query_as(
+        "SELECT id, url FROM repositories WHERE id NOT IN (SELECT DISTINCT repository_id FROM subscriptions)",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut removed = Vec::new();
+    for (repo_id, url) in orphans {
+        // Someone may have subscribed since the orphans were listed.
+        let result = sqlx::query(
+            "DELETE FROM repositories
+             WHERE id = ? AND NOT EXISTS (SELECT 1 FROM subscriptions WHERE repository_id = ?)",
+        )
+        .bind(repo_id)
+        .bind(repo_id)
         .execute(pool)
         .await?;
-    Ok(result.rows_affected())
+        if result.rows_affected() > 0 {
+            removed.push(url);
+        }
+    }
+    Ok(removed)
 }
 
-pub async fn remove_orphan_users(pool: &DbPool) -> Result<u64, DbError> {
+pub async fn remove_orphan_chats(pool: &DbPool) -> Result<u64, DbError> {
     let result = sqlx::query(
-        "DELETE FROM users WHERE id NOT IN (SELECT DISTINCT user_id FROM subscriptions)",
+        "DELETE FROM chats WHERE id NOT IN (SELECT DISTINCT chat_id FROM subscriptions)",
     )
     .execute(pool)
     .await?;
@@ -134,8 +285,193 @@ pub async fn remove_repository(pool: &DbPool, repo_id: i32) -> Result<(), DbErro
     Ok(())
 }
 
-pub async fn remove_user(pool: &DbPool, user_id: i64) -> Result<(), DbError> {
-    sqlx::query!("DELETE FROM users WHERE id = ?", user_id)
+pub async fn get_repository_id_by_url(pool: &DbPool, repo_url: &str) -> Result<Option<i32>, DbError> {
+    let url_hash = format!("{:x}", Sha256::digest(repo_url.as_bytes()));
+    let row = sqlx::query!("SELECT id FROM repositories WHERE url_hash = ?", url_hash)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|row| row.id))
+}
+
+pub async fn set_repository_url(pool: &DbPool, repo_id: i32, repo_url: &str) -> Result<(), DbError> {
+    let url_hash = format!("{:x}", Sha256::digest(repo_url.as_bytes()));
+    sqlx::query!(
+        "UPDATE repositories SET url = ?, url_hash = ? WHERE id = ?",
+        repo_url,
+        url_hash,
+        repo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Moves subscriptions (and their filters, through the cascading key) onto another
+// repository. A chat subscribed to both keeps the subscription it already had there.
+pub async fn merge_repository(pool: &DbPool, from_repo_id: i32, into_repo_id: i32) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE IGNORE subscriptions SET repository_id = ? WHERE repository_id = ?",
+        into_repo_id,
+        from_repo_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM repositories WHERE id = ?", from_repo_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// Once anyone attaches credentials the repository is treated as private, and only
+// subscriptions whose credentials are known to work receive its events.
+pub async fn set_subscription_credentials(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+    sealed_credentials: &[u8],
+) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE subscriptions SET credentials = ?, credentials_valid = TRUE
+         WHERE chat_id = ? AND repository_id = ?",
+        sealed_credentials,
+        chat_id,
+        repo_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE repositories SET requires_credentials = TRUE WHERE id = ?",
+        repo_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_repository_credentials(
+    pool: &DbPool,
+    repo_id: i32,
+) -> Result<Vec<StoredCredentials>, DbError> {
+    let rows = sqlx::query!(
+        "SELECT s.chat_id, s.credentials, s.credentials_valid FROM subscriptions s
+         JOIN repositories r ON s.repository_id = r.id
+         WHERE s.repository_id = ? AND r.requires_credentials = TRUE AND s.credentials IS NOT NULL",
+        repo_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(StoredCredentials {
+                chat_id: row.chat_id,
+                sealed: row.credentials?,
+                valid: row.credentials_valid == 1,
+            })
+        })
+        .collect())
+}
+
+// A repository that went public keeps the credentials its subscribers left, they're
+// put back to use once anonymous access is refused again. Returns whether any exist.
+pub async fn reinstate_credentials_requirement(
+    pool: &DbPool,
+    repo_id: i32,
+) -> Result<bool, DbError> {
+    let result = sqlx::query!(
+        "UPDATE repositories SET requires_credentials = TRUE, credentials_checked_at = NULL
+         WHERE id = ? AND requires_credentials = FALSE AND EXISTS (
+             SELECT 1 FROM subscriptions
+             WHERE repository_id = ? AND credentials IS NOT NULL
+         )",
+        repo_id,
+        repo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_credentials_valid(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+    valid: bool,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE subscriptions SET credentials_valid = ? WHERE chat_id = ? AND repository_id = ?",
+        valid,
+        chat_id,
+        repo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn record_credentials_check(
+    pool: &DbPool,
+    repo_id: i32,
+    requires_credentials: bool,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE repositories SET requires_credentials = ?, credentials_checked_at = NOW() WHERE id = ?",
+        requires_credentials,
+        repo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Telegram gives a group a new id when it is upgraded to a supergroup.
+pub async fn migrate_chat(pool: &DbPool, old_chat_id: i64, new_chat_id: i64) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT IGNORE INTO chats (id, kind, title, username, notifications_enabled, delivery_mode, digest_time,
+                                   timezone, quiet_hours_start, quiet_hours_end, quiet_hours_mode)
+         SELECT ?, 'supergroup', title, username, notifications_enabled, delivery_mode, digest_time,
+                timezone, quiet_hours_start, quiet_hours_end, quiet_hours_mode
+         FROM chats WHERE id = ?",
+        new_chat_id,
+        old_chat_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE IGNORE subscriptions SET chat_id = ? WHERE chat_id = ?",
+        new_chat_id,
+        old_chat_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE notification_outbox SET chat_id = ? WHERE chat_id = ?",
+        new_chat_id,
+        old_chat_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE digest_entries SET chat_id = ? WHERE chat_id = ?",
+        new_chat_id,
+        old_chat_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM chats WHERE id = ?", old_chat_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn remove_chat(pool: &DbPool, chat_id: i64) -> Result<(), DbError> {
+    sqlx::query!("DELETE FROM chats WHERE id = ?", chat_id)
         .execute(pool)
         .await?;
     Ok(())
@@ -159,82 +495,202 @@ pub async fn get_all_repositories(pool: &DbPool) -> Result<Vec<Repository>, DbEr
     Ok(repos)
 }
 
-pub async fn get_user_subscriptions(
+// Repositories whose webhook delivered recently are left alone, polling resumes
+// once every hook has been silent for the given number of minutes.
+pub async fn get_due_repositories(
     pool: &DbPool,
-    user_id: i64,
-) -> Result<Vec<Repository>, DbError> {
-    let repos = sqlx::query_as::<_, Repository>(
-        "SELECT r.id, r.url FROM repositories r
-         JOIN subscriptions s ON r.id = s.repository_id
-         WHERE s.user_id = ?",
+    webhook_stale_mins: u64,
+) -> Result<Vec<ScheduledRepository>, DbError> {
+    let repos = sqlx::query_as::<_, ScheduledRepository>(
+        "SELECT id, url, consecutive_failures, unreachable_notified, requires_credentials,
+                TIMESTAMPDIFF(SECOND, last_change_at, NOW()) AS idle_secs,
+                TIMESTAMPDIFF(SECOND, first_failure_at, NOW()) AS failing_secs,
+                TIMESTAMPDIFF(SECOND, credentials_checked_at, NOW()) AS credentials_age_secs
+         FROM repositories r
+         WHERE next_check_at <= NOW()
+           AND NOT EXISTS (
+               SELECT 1 FROM subscriptions s
+               WHERE s.repository_id = r.id
+                 AND s.webhook_delivered_at > NOW() - INTERVAL ? MINUTE
+           )",
     )
-    .bind(user_id)
+    .bind(webhook_stale_mins)
     .fetch_all(pool)
     .await?;
     Ok(repos)
 }
 
-pub async fn get_repository_refs(
+pub async fn record_poll_success(
     pool: &DbPool,
     repo_id: i32,
-) -> Result<HashMap<String, String>, DbError> {
-    let refs = sqlx::query!(
-        "SELECT ref_name, last_hash FROM repository_refs WHERE repository_id = ?",
+    changed: bool,
+    delay_secs: u64,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE repositories
+         SET consecutive_failures = 0,
+             first_failure_at = NULL,
+             unreachable_notified = FALSE,
+             last_change_at = IF(?, NOW(), last_change_at),
+             next_check_at = NOW() + INTERVAL ? SECOND
+         WHERE id = ?",
+        changed,
+        delay_secs,
         repo_id
     )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|rec| (rec.ref_name, rec.last_hash))
-    .collect();
-    Ok(refs)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn record_poll_failure(pool: &DbPool, repo_id: i32, delay_secs: u64) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE repositories
+         SET consecutive_failures = consecutive_failures + 1,
+             first_failure_at = COALESCE(first_failure_at, NOW()),
+             next_check_at = NOW() + INTERVAL ? SECOND
+         WHERE id = ?",
+        delay_secs,
+        repo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_unreachable_notified(pool: &DbPool, repo_id: i32) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE repositories SET unreachable_notified = TRUE WHERE id = ?",
+        repo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn extend_repository_grace(pool: &DbPool, repo_id: i32) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE repositories SET first_failure_at = NOW(), unreachable_notified = FALSE
+         WHERE id = ? AND first_failure_at IS NOT NULL",
+        repo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
 }
 
-pub async fn update_ref_hash(
+pub async fn get_webhook_secret(
     pool: &DbPool,
+    chat_id: i64,
     repo_id: i32,
-    ref_name: &str,
-    new_hash: &str,
+) -> Result<Option<String>, DbError> {
+    let record = sqlx::query!(
+        "SELECT webhook_secret FROM subscriptions WHERE chat_id = ? AND repository_id = ?",
+        chat_id,
+        repo_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(record.and_then(|rec| rec.webhook_secret))
+}
+
+pub async fn set_webhook_secret(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+    secret: &str,
 ) -> Result<(), DbError> {
     sqlx::query!(
-        "INSERT INTO repository_refs (repository_id, ref_name, last_hash) VALUES (?, ?, ?)
-         ON DUPLICATE KEY UPDATE last_hash = VALUES(last_hash)",
-        repo_id,
-        ref_name,
-        new_hash
+        "UPDATE subscriptions SET webhook_secret = ?, webhook_delivered_at = NULL
+         WHERE chat_id = ? AND repository_id = ?",
+        secret,
+        chat_id,
+        repo_id
     )
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn delete_ref(pool: &DbPool, repo_id: i32, ref_name: &str) -> Result<(), DbError> {
+pub async fn record_webhook_delivery(pool: &DbPool, chat_id: i64, repo_id: i32) -> Result<(), DbError> {
     sqlx::query!(
-        "DELETE FROM repository_refs WHERE repository_id = ? AND ref_name = ?",
-        repo_id,
-        ref_name
+        "UPDATE subscriptions SET webhook_delivered_at = NOW()
+         WHERE chat_id = ? AND repository_id = ?",
+        chat_id,
+        repo_id
     )
     .execute(pool)
     .await?;
     Ok(())
 }
 
+pub async fn get_chat_subscriptions(
+    pool: &DbPool,
+    chat_id: i64,
+) -> Result<Vec<Repository>, DbError> {
+    let repos = sqlx::query_as::<_, Repository>(
+        "SELECT r.id, r.url FROM repositories r
+         JOIN subscriptions s ON r.id = s.repository_id
+         WHERE s.chat_id = ?",
+    )
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(repos)
+}
+
+pub async fn get_repository_refs(
+    pool: &DbPool,
+    repo_id: i32,
+) -> Result<HashMap<String, String>, DbError> {
+    let refs = sqlx::query!(
+        "SELECT ref_name, last_hash FROM repository_refs WHERE repository_id = ?",
+        repo_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|rec| (rec.ref_name, rec.last_hash))
+    .collect();
+    Ok(refs)
+}
+
 pub async fn get_subscribers_with_settings(
     pool: &DbPool,
     repo_id: i32,
-) -> Result<HashMap<ChatId, SubscriptionSettings>, DbError> {
+) -> Result<HashMap<ChatId, Subscriber>, DbError> {
     let records = sqlx::query!(
         r#"
         SELECT
-            u.id,
+            c.id,
             s.notify_on_new_branch,
             s.notify_on_new_tag,
             s.notify_on_branch_update,
             s.notify_on_new_pr,
-            s.notify_on_pr_update
+            s.notify_on_pr_update,
+            s.notify_on_force_push,
+            s.notify_on_branch_delete,
+            s.notify_on_tag_delete,
+            s.notify_on_pr_close,
+            s.include_tag_changelog,
+            s.release_filter,
+            s.delivery_mode AS subscription_delivery_mode,
+            s.message_thread_id,
+            s.branch_thread_id,
+            s.tag_thread_id,
+            s.pr_thread_id,
+            s.muted_until,
+            s.credentials_valid,
+            r.requires_credentials,
+            c.delivery_mode AS chat_delivery_mode,
+            c.timezone,
+            c.quiet_hours_start,
+            c.quiet_hours_end,
+            c.quiet_hours_mode
         FROM subscriptions s
-        JOIN users u ON s.user_id = u.id
-        WHERE s.repository_id = ? AND u.notifications_enabled = TRUE
+        JOIN chats c ON s.chat_id = c.id
+        JOIN repositories r ON s.repository_id = r.id
+        WHERE s.repository_id = ? AND c.notifications_enabled = TRUE
         "#,
         repo_id
     )
@@ -249,31 +705,65 @@ pub async fn get_subscribers_with_settings(
             notify_on_branch_update: record.notify_on_branch_update == 1,
             notify_on_new_pr: record.notify_on_new_pr == 1,
             notify_on_pr_update: record.notify_on_pr_update == 1,
+            notify_on_force_push: record.notify_on_force_push == 1,
+            notify_on_branch_delete: record.notify_on_branch_delete == 1,
+            notify_on_tag_delete: record.notify_on_tag_delete == 1,
+            notify_on_pr_close: record.notify_on_pr_close == 1,
+            include_tag_changelog: record.include_tag_changelog == 1,
+            release_filter: ReleaseFilter::from_db(&record.release_filter).unwrap_or_default(),
+            delivery_mode: record
+                .subscription_delivery_mode
+                .as_deref()
+                .and_then(DeliveryMode::from_db),
         };
-        subscribers.insert(ChatId(record.id), settings);
+        let delivery_mode = settings
+            .delivery_mode
+            .or_else(|| DeliveryMode::from_db(&record.chat_delivery_mode))
+            .unwrap_or_default();
+        subscribers.insert(
+            ChatId(record.id),
+            Subscriber {
+                settings,
+                delivery_mode,
+                quiet_hours: QuietHours::from_db(
+                    record.quiet_hours_start,
+                    record.quiet_hours_end,
+                    &record.quiet_hours_mode,
+                ),
+                timezone: record.timezone,
+                topics: TopicRoutes {
+                    default: record.message_thread_id,
+                    branch: record.branch_thread_id,
+                    tag: record.tag_thread_id,
+                    pull_request: record.pr_thread_id,
+                },
+                muted_until: record.muted_until,
+                authorized: record.requires_credentials == 0 || record.credentials_valid == 1,
+            },
+        );
     }
     Ok(subscribers)
 }
 
-pub async fn get_user_notification_status(pool: &DbPool, user_id: i64) -> Result<bool, DbError> {
+pub async fn get_chat_notification_status(pool: &DbPool, chat_id: i64) -> Result<bool, DbError> {
     let result = sqlx::query!(
-        "SELECT notifications_enabled FROM users WHERE id = ?",
-        user_id
+        "SELECT notifications_enabled FROM chats WHERE id = ?",
+        chat_id
     )
     .fetch_one(pool)
     .await?;
     Ok(result.notifications_enabled == 1)
 }
 
-pub async fn set_user_notification_status(
+pub async fn set_chat_notification_status(
     pool: &DbPool,
-    user_id: i64,
+    chat_id: i64,
     status: bool,
 ) -> Result<(), DbError> {
     sqlx::query!(
-        "UPDATE users SET notifications_enabled = ? WHERE id = ?",
+        "UPDATE chats SET notifications_enabled = ? WHERE id = ?",
         status,
-        user_id
+        chat_id
     )
     .execute(pool)
     .await?;
@@ -282,7 +772,7 @@ pub async fn set_user_notification_status(
 
 pub async fn get_subscription_settings(
     pool: &DbPool,
-    user_id: i64,
+    chat_id: i64,
     repo_id: i32,
 ) -> Result<SubscriptionSettings, DbError> {
     let record = sqlx::query!(
@@ -292,11 +782,18 @@ pub async fn get_subscription_settings(
             notify_on_new_tag,
             notify_on_branch_update,
             notify_on_new_pr,
-            notify_on_pr_update
+            notify_on_pr_update,
+            notify_on_force_push,
+            notify_on_branch_delete,
+            notify_on_tag_delete,
+            notify_on_pr_close,
+            include_tag_changelog,
+            release_filter,
+            delivery_mode
         FROM subscriptions
-        WHERE user_id = ? AND repository_id = ?
+        WHERE chat_id = ? AND repository_id = ?
         "#,
-        user_id,
+        chat_id,
         repo_id
     )
     .fetch_one(pool)
@@ -308,25 +805,667 @@ pub async fn get_subscription_settings(
         notify_on_branch_update: record.notify_on_branch_update == 1,
         notify_on_new_pr: record.notify_on_new_pr == 1,
         notify_on_pr_update: record.notify_on_pr_update == 1,
+        notify_on_force_push: record.notify_on_force_push == 1,
+        notify_on_branch_delete: record.notify_on_branch_delete == 1,
+        notify_on_tag_delete: record.notify_on_tag_delete == 1,
+        notify_on_pr_close: record.notify_on_pr_close == 1,
+        include_tag_changelog: record.include_tag_changelog == 1,
+        release_filter: ReleaseFilter::from_db(&record.release_filter).unwrap_or_default(),
+        delivery_mode: record.delivery_mode.as_deref().and_then(DeliveryMode::from_db),
     })
 }
 
 pub async fn update_subscription_settings(
     pool: &DbPool,
-    user_id: i64,
+    chat_id: i64,
     repo_id: i32,
     settings: &SubscriptionSettings,
 ) -> Result<(), DbError> {
     sqlx::query!(
         "UPDATE subscriptions
-         SET notify_on_new_branch = ?, notify_on_new_tag = ?, notify_on_branch_update = ?, notify_on_new_pr = ?, notify_on_pr_update = ?
-         WHERE user_id = ? AND repository_id = ?",
+         SET notify_on_new_branch = ?, notify_on_new_tag = ?, notify_on_branch_update = ?, notify_on_new_pr = ?, notify_on_pr_update = ?, notify_on_force_push = ?,
+             notify_on_branch_delete = ?, notify_on_tag_delete = ?, notify_on_pr_close = ?,
+             include_tag_changelog = ?, release_filter = ?, delivery_mode = ?
+         WHERE chat_id = ? AND repository_id = ?",
         settings.notify_on_new_branch,
         settings.notify_on_new_tag,
         settings.notify_on_branch_update,
         settings.notify_on_new_pr,
         settings.notify_on_pr_update,
-        user_id,
+        settings.notify_on_force_push,
+        settings.notify_on_branch_delete,
+        settings.notify_on_tag_delete,
+        settings.notify_on_pr_close,
+        settings.include_tag_changelog,
+        settings.release_filter.as_str(),
+        settings.delivery_mode.map(|mode| mode.as_str()),
+        chat_id,
+        repo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_subscription_filters(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+) -> Result<Vec<RefFilter>, DbError> {
+    let filters = sqlx::query!(
+        "SELECT id, ref_kind, mode, pattern FROM subscription_filters
+         WHERE chat_id = ? AND repository_id = ?
+         ORDER BY id",
+        chat_id,
+        repo_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .filter_map(|rec| {
+        Some(RefFilter {
+            id: rec.id,
+            ref_kind: RefKind::from_db(&rec.ref_kind)?,
+            mode: FilterMode::from_db(&rec.mode)?,
+            pattern: rec.pattern,
+        })
+    })
+    .collect();
+    Ok(filters)
+}
+
+pub async fn get_repository_filters(
+    pool: &DbPool,
+    repo_id: i32,
+) -> Result<HashMap<ChatId, Vec<RefFilter>>, DbError> {
+    let records = sqlx::query!(
+        "SELECT id, chat_id, ref_kind, mode, pattern FROM subscription_filters
+         WHERE repository_id = ?",
+        repo_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut filters: HashMap<ChatId, Vec<RefFilter>> = HashMap::new();
+    for record in records {
+        let (Some(ref_kind), Some(mode)) = (
+            RefKind::from_db(&record.ref_kind),
+            FilterMode::from_db(&record.mode),
+        ) else {
+            continue;
+        };
+        filters.entry(ChatId(record.chat_id)).or_default().push(RefFilter {
+            id: record.id,
+            ref_kind,
+            mode,
+            pattern: record.pattern,
+        });
+    }
+    Ok(filters)
+}
+
+pub async fn add_subscription_filter(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+    ref_kind: RefKind,
+    mode: FilterMode,
+    pattern: &str,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "INSERT INTO subscription_filters (chat_id, repository_id, ref_kind, mode, pattern)
+         VALUES (?, ?, ?, ?, ?)",
+        chat_id,
+        repo_id,
+        ref_kind.as_str(),
+        mode.as_str(),
+        pattern
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn remove_subscription_filter(
+    pool: &DbPool,
+    chat_id: i64,
+    filter_id: i32,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "DELETE FROM subscription_filters WHERE id = ? AND chat_id = ?",
+        filter_id,
+        chat_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn commit_event(
+    pool: &DbPool,
+    repo_id: i32,
+    changes: &[RefChange],
+    notifications: &[Notification],
+    digest_entries: &[DigestEntry],
+) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+
+    for change in changes {
+        match change {
+            RefChange::Update { ref_name, sha } => {
+                sqlx::query!(
+                    "INSERT INTO repository_refs (repository_id, ref_name, last_hash) VALUES (?, ?, ?)
+                     ON DUPLICATE KEY UPDATE last_hash = VALUES(last_hash)",
+                    repo_id,
+                    ref_name,
+                    sha
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            RefChange::Delete { ref_name } => {
+                sqlx::query!(
+                    "DELETE FROM repository_refs WHERE repository_id = ? AND ref_name = ?",
+                    repo_id,
+                    ref_name
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            RefChange::Settled { ref_name, sha } => {
+                // A push that was held after this update was read starts where it ended.
+                sqlx::query!(
+                    "UPDATE pending_branch_updates SET old_sha = ?
+                     WHERE repository_id = ? AND ref_name = ? AND new_sha <> ?",
+                    sha,
+                    repo_id,
+                    ref_name,
+                    sha
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!(
+                    "DELETE FROM pending_branch_updates
+                     WHERE repository_id = ? AND ref_name = ? AND new_sha = ?",
+                    repo_id,
+                    ref_name,
+                    sha
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+    }
+
+    insert_notifications(&mut tx, notifications).await?;
+
+    for entry in digest_entries {
+        let event = serde_json::to_string(&entry.event).unwrap_or_default();
+        sqlx::query!(
+            "INSERT INTO digest_entries (chat_id, message_thread_id, repo_url, event, delivery_mode)
+             VALUES (?, ?, ?, ?, ?)",
+            entry.chat_id.0,
+            entry.thread_id,
+            entry.repo_url,
+            event,
+            entry.delivery_mode.as_str()
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn enqueue_notifications(
+    pool: &DbPool,
+    notifications: &[Notification],
+) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    insert_notifications(&mut tx, notifications).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn insert_notifications(
+    tx: &mut sqlx::Transaction<'_, sqlx::MySql>,
+    notifications: &[Notification],
+) -> Result<(), DbError> {
+    for notification in notifications {
+        let reply_markup = notification
+            .reply_markup
+            .as_ref()
+            .and_then(|markup| serde_json::to_string(markup).ok());
+        sqlx::query!(
+            "INSERT INTO notification_outbox
+                 (chat_id, message_thread_id, message, reply_markup, disable_notification, next_attempt_at)
+             VALUES (?, ?, ?, ?, ?, COALESCE(?, CURRENT_TIMESTAMP))",
+            notification.chat_id.0,
+            notification.thread_id,
+            notification.text,
+            reply_markup,
+            notification.disable_notification,
+            notification.deliver_at
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn get_due_notifications(pool: &DbPool, limit: u32) -> Result<Vec<OutboxMessage>, DbError> {
+    let notifications = sqlx::query_as::<_, OutboxMessage>(
+        "SELECT id, chat_id, message_thread_id, message, reply_markup, disable_notification, attempts
+         FROM notification_outbox
+         WHERE next_attempt_at <= NOW()
+         ORDER BY id
+         LIMIT ?",
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(notifications)
+}
+
+pub async fn delete_notification(pool: &DbPool, notification_id: i64) -> Result<(), DbError> {
+    sqlx::query!("DELETE FROM notification_outbox WHERE id = ?", notification_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// A deleted forum topic can't be posted to again, everything routed there goes to
+// the main chat instead.
+pub async fn forget_topic(pool: &DbPool, chat_id: i64, thread_id: i32) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE subscriptions
+         SET message_thread_id = NULLIF(message_thread_id, ?),
+             branch_thread_id = NULLIF(branch_thread_id, ?),
+             tag_thread_id = NULLIF(tag_thread_id, ?),
+             pr_thread_id = NULLIF(pr_thread_id, ?)
+         WHERE chat_id = ?",
+        thread_id,
+        thread_id,
+        thread_id,
+        thread_id,
+        chat_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE notification_outbox SET message_thread_id = NULL
+         WHERE chat_id = ? AND message_thread_id = ?",
+        chat_id,
+        thread_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE digest_entries SET message_thread_id = NULL
+         WHERE chat_id = ? AND message_thread_id = ?",
+        chat_id,
+        thread_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn clear_notification_thread(pool: &DbPool, notification_id: i64) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE notification_outbox SET message_thread_id = NULL WHERE id = ?",
+        notification_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn reschedule_notification(
+    pool: &DbPool,
+    notification_id: i64,
+    delay_secs: u64,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE notification_outbox
+         SET attempts = attempts + 1, next_attempt_at = NOW() + INTERVAL ? SECOND
+         WHERE id = ?",
+        delay_secs,
+        notification_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Rate limits aren't failures: nothing counts as an attempt, and the whole chat waits
+// so its messages still arrive in order.
+pub async fn postpone_chat_notifications(
+    pool: &DbPool,
+    chat_id: i64,
+    delay_secs: u64,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE notification_outbox
+         SET next_attempt_at = GREATEST(next_attempt_at, NOW() + INTERVAL ? SECOND)
+         WHERE chat_id = ?",
+        delay_secs,
+        chat_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_pending_digest_groups(pool: &DbPool) -> Result<Vec<DigestGroup>, DbError> {
+    let groups = sqlx::query_as::<_, DigestGroup>(
+        "SELECT e.chat_id, e.delivery_mode, e.message_thread_id, MIN(e.created_at) AS oldest_entry_at, c.timezone, c.digest_time,
+                c.quiet_hours_start, c.quiet_hours_end, c.quiet_hours_mode
+         FROM digest_entries e
+         JOIN chats c ON c.id = e.chat_id
+         GROUP BY e.chat_id, e.delivery_mode, e.message_thread_id, c.timezone, c.digest_time,
+                  c.quiet_hours_start, c.quiet_hours_end, c.quiet_hours_mode",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(groups)
+}
+
+pub async fn get_digest_entries(
+    pool: &DbPool,
+    chat_id: i64,
+    delivery_mode: DeliveryMode,
+    thread_id: Option<i32>,
+) -> Result<Vec<StoredDigestEntry>, DbError> {
+    let entries = sqlx::query_as::<_, StoredDigestEntry>(
+        "SELECT id, repo_url, event FROM digest_entries
+         WHERE chat_id = ? AND delivery_mode = ? AND message_thread_id <=> ?
+         ORDER BY id",
+    )
+    .bind(chat_id)
+    .bind(delivery_mode.as_str())
+    .bind(thread_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(entries)
+}
+
+pub async fn commit_digest(
+    pool: &DbPool,
+    entry_ids: &[i64],
+    notifications: &[Notification],
+) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    insert_notifications(&mut tx, notifications).await?;
+    for entry_id in entry_ids {
+        sqlx::query!("DELETE FROM digest_entries WHERE id = ?", entry_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_delivery_preferences(
+    pool: &DbPool,
+    chat_id: i64,
+) -> Result<DeliveryPreferences, DbError> {
+    let record = sqlx::query!(
+        "SELECT delivery_mode, digest_time, timezone, quiet_hours_start, quiet_hours_end, quiet_hours_mode
+         FROM chats WHERE id = ?",
+        chat_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(DeliveryPreferences {
+        delivery_mode: DeliveryMode::from_db(&record.delivery_mode).unwrap_or_default(),
+        digest_time: record.digest_time,
+        timezone: record.timezone,
+        quiet_hours: QuietHours::from_db(
+            record.quiet_hours_start,
+            record.quiet_hours_end,
+            &record.quiet_hours_mode,
+        ),
+        quiet_hours_mode: QuietHoursMode::from_db(&record.quiet_hours_mode).unwrap_or_default(),
+    })
+}
+
+pub async fn set_chat_delivery_mode(
+    pool: &DbPool,
+    chat_id: i64,
+    delivery_mode: DeliveryMode,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE chats SET delivery_mode = ? WHERE id = ?",
+        delivery_mode.as_str(),
+        chat_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_chat_digest_time(
+    pool: &DbPool,
+    chat_id: i64,
+    digest_time: NaiveTime,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE chats SET digest_time = ? WHERE id = ?",
+        digest_time,
+        chat_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_chat_timezone(pool: &DbPool, chat_id: i64, timezone: &str) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE chats SET timezone = ? WHERE id = ?",
+        timezone,
+        chat_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_chat_quiet_hours(
+    pool: &DbPool,
+    chat_id: i64,
+    window: Option<(NaiveTime, NaiveTime)>,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE chats SET quiet_hours_start = ?, quiet_hours_end = ? WHERE id = ?",
+        window.map(|(start, _)| start),
+        window.map(|(_, end)| end),
+        chat_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_chat_quiet_hours_mode(
+    pool: &DbPool,
+    chat_id: i64,
+    mode: QuietHoursMode,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE chats SET quiet_hours_mode = ? WHERE id = ?",
+        mode.as_str(),
+        chat_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_subscription_muted_until(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+) -> Result<Option<DateTime<Utc>>, DbError> {
+    let record = sqlx::query!(
+        "SELECT muted_until FROM subscriptions
+         WHERE chat_id = ? AND repository_id = ? AND muted_until > NOW()",
+        chat_id,
+        repo_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(record.and_then(|record| record.muted_until))
+}
+
+pub async fn set_subscription_muted_until(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+    muted_until: Option<DateTime<Utc>>,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE subscriptions SET muted_until = ? WHERE chat_id = ? AND repository_id = ?",
+        muted_until,
+        chat_id,
+        repo_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn hold_branch_update(
+    pool: &DbPool,
+    repo_id: i32,
+    ref_name: &str,
+    old_sha: &str,
+    new_sha: &str,
+) -> Result<(), DbError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "INSERT INTO repository_refs (repository_id, ref_name, last_hash) VALUES (?, ?, ?)
+         ON DUPLICATE KEY UPDATE last_hash = VALUES(last_hash)",
+        repo_id,
+        ref_name,
+        new_sha
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO pending_branch_updates (repository_id, ref_name, old_sha, new_sha) VALUES (?, ?, ?, ?)
+         ON DUPLICATE KEY UPDATE new_sha = VALUES(new_sha), last_seen_at = NOW()",
+        repo_id,
+        ref_name,
+        old_sha,
+        new_sha
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_settled_branch_updates(
+    pool: &DbPool,
+    settle_secs: u64,
+    max_hold_secs: u64,
+) -> Result<Vec<PendingBranchUpdate>, DbError> {
+    let updates = sqlx::query_as::<_, PendingBranchUpdate>(
+        "SELECT r.id, r.url, p.ref_name, p.old_sha, p.new_sha
+         FROM pending_branch_updates p
+         JOIN repositories r ON r.id = p.repository_id
+         WHERE p.last_seen_at <= NOW() - INTERVAL ? SECOND
+            OR p.first_seen_at <= NOW() - INTERVAL ? SECOND
+         ORDER BY r.id, p.first_seen_at",
+    )
+    .bind(settle_secs)
+    .bind(max_hold_secs)
+    .fetch_all(pool)
+    .await?;
+    Ok(updates)
+}
+
+pub async fn get_subscription_topics(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+) -> Result<TopicRoutes, DbError> {
+    let record = sqlx::query!(
+        "SELECT message_thread_id, branch_thread_id, tag_thread_id, pr_thread_id
+         FROM subscriptions WHERE chat_id = ? AND repository_id = ?",
+        chat_id,
+        repo_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(TopicRoutes {
+        default: record.message_thread_id,
+        branch: record.branch_thread_id,
+        tag: record.tag_thread_id,
+        pull_request: record.pr_thread_id,
+    })
+}
+
+pub async fn set_subscription_topic(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+    category: Option<EventCategory>,
+    thread_id: Option<i32>,
+) -> Result<(), DbError> {
+    match category {
+        None => sqlx::query!(
+            "UPDATE subscriptions SET message_thread_id = ? WHERE chat_id = ? AND repository_id = ?",
+            thread_id,
+            chat_id,
+            repo_id
+        )
+        .execute(pool)
+        .await?,
+        Some(EventCategory::Branch) => sqlx::query!(
+            "UPDATE subscriptions SET branch_thread_id = ? WHERE chat_id = ? AND repository_id = ?",
+            thread_id,
+            chat_id,
+            repo_id
+        )
+        .execute(pool)
+        .await?,
+        Some(EventCategory::Tag) => sqlx::query!(
+            "UPDATE subscriptions SET tag_thread_id = ? WHERE chat_id = ? AND repository_id = ?",
+            thread_id,
+            chat_id,
+            repo_id
+        )
+        .execute(pool)
+        .await?,
+        Some(EventCategory::PullRequest) => sqlx::query!(
+            "UPDATE subscriptions SET pr_thread_id = ? WHERE chat_id = ? AND repository_id = ?",
+            thread_id,
+            chat_id,
+            repo_id
+        )
+        .execute(pool)
+        .await?,
+    };
+    Ok(())
+}
+
+pub async fn reset_subscription_topics(
+    pool: &DbPool,
+    chat_id: i64,
+    repo_id: i32,
+) -> Result<(), DbError> {
+    sqlx::query!(
+        "UPDATE subscriptions
+         SET message_thread_id = NULL, branch_thread_id = NULL, tag_thread_id = NULL, pr_thread_id = NULL
+         WHERE chat_id = ? AND repository_id = ?",
+        chat_id,
         repo_id
     )
     .execute(pool)
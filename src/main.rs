@@ -1,60 +1,155 @@
 mod bot;
 mod core;
 mod infrastructure;
+mod webhook;
 
 use crate::bot::dialogue::{Dialogue, InMemStorage, State};
-use crate::bot::ui::{global_notification_toggle_menu, notification_settings_menu, repository_menu, subscriptions_menu};
+use crate::bot::permissions::can_manage_subscriptions;
+use crate::bot::AppBot;
+use crate::bot::ui::{delivery_settings_menu, filters_menu, global_notification_toggle_menu, notification_settings_menu, quiet_hours_menu, repository_menu, snooze_menu, subscriptions_menu, topic_routing_menu, webhook_menu};
+use crate::core::backup::{self, ImportOutcome, SubscriptionExport};
+use crate::core::credentials::{self, Credentials};
+use crate::core::delivery;
+use crate::core::digest::{self, DeliveryMode};
+use crate::core::events::TrackedRef;
+use crate::core::filters::{self, FilterMode, RefKind};
+use crate::core::forge::Forge;
+use crate::core::git_service;
+use crate::core::quiet_hours::QuietHours;
+use crate::core::topics::{EventCategory, TopicRoutes};
 use crate::core::updater;
+use crate::infrastructure::config;
 use crate::infrastructure::db::{self, DbPool};
 use crate::infrastructure::logging::init_logging;
 use anyhow::anyhow;
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use chrono_tz::Tz;
 use dotenv::dotenv;
+use std::sync::Arc;
+use std::time::Instant;
+use teloxide::adaptors::throttle::Limits;
 use teloxide::dptree;
 use teloxide::prelude::*;
-use teloxide::types::{MessageId, ParseMode};
+use teloxide::net::Download;
+use teloxide::types::{ForceReply, InputFile, MessageId, MessageKind, ParseMode};
 use teloxide::utils::command::BotCommands;
 use teloxide::utils::markdown::escape;
 use teloxide::RequestError;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase", description = "These commands are supported:")]
 enum Command {
     #[command(description = "List your subscriptions.")]
     ListRepos,
-    #[command(description = "Add a new repository subscription.")]
-    AddRepo,
+    #[command(description = "Add a new repository subscription, optionally followed by one or more URLs.")]
+    AddRepo(String),
+    #[command(description = "Subscribe to a private repository with an access token or deploy key.")]
+    AddPrivate(String),
     #[command(description = "Toggle all notifications on/off.")]
     Toggle,
+    #[command(description = "Choose immediate notifications or digests.")]
+    Delivery,
+    #[command(description = "Set up quiet hours.")]
+    Quiet,
+    #[command(description = "Export your subscriptions as a JSON file.")]
+    Export,
+    #[command(description = "Import subscriptions from an exported JSON file.")]
+    Import,
 }
 
 type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 
+const ADMINS_ONLY: &str = "🔒 Only chat administrators can manage subscriptions here.";
+
+const NOT_SUBSCRIBED: &str = "🔒 This chat is not subscribed to that repository.";
+
+// Buttons that act on one repository carry its id right after their prefix.
+const REPOSITORY_CALLBACKS: [&str; 15] = [
+    "view_repo_",
+    "unsubscribe_",
+    "keep_trying_",
+    "repo_settings_",
+    "toggle_setting_",
+    "repo_snooze_",
+    "snooze_",
+    "repo_topics_",
+    "route_here_",
+    "route_reset_",
+    "repo_filters_",
+    "add_filter_",
+    "remove_filter_",
+    "repo_webhook_",
+    "webhook_regen_",
+];
+
+const MAX_IMPORT_FILE_SIZE: u32 = 1024 * 1024;
+
+const MAX_CONCURRENT_IMPORTS: usize = 8;
+
+const IMPORT_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3);
+
+const FILTERS_HELP: &str = "🔍 Branch & tag filters for this repository.\n\n\
+Include filters limit notifications to matching refs, exclude filters drop matching refs. \
+Patterns are globs (* within one path segment, ** across segments, ?, [0-9]); \
+prefix a pattern with re: to use a regular expression, e.g. re:v[0-9]+\\..*";
+
+const DELIVERY_HELP: &str = "📬 How should notifications be delivered?\n\n\
+Digests collect events and send one summary per hour, or once a day at your digest time. \
+Individual repositories can override this in their notification settings.";
+
+const CREDENTIALS_HELP: &str = "🔐 Send me the credentials for this repository:\n\n\
+• an access token, or username:token if your forge needs the username\n\
+• or the private SSH deploy key\n\n\
+They are stored encrypted, and only subscriptions with working credentials receive its notifications. \
+Your message will be deleted right away.";
+
+const QUIET_HOURS_HELP: &str = "🌙 Quiet hours in your timezone.\n\n\
+Events arriving during quiet hours can be delivered one by one when they end, \
+summarized into a single message, or sent right away without sound.";
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     let _guard = init_logging();
     log::info!("Starting bot...");
+    git_service::init_network_timeouts();
+    if !credentials::is_configured() {
+        log::info!("CREDENTIALS_KEY is not configured, private repositories are disabled");
+    }
 
-    let bot = Bot::from_env();
+    let bot: AppBot = Bot::from_env().throttle(Limits::default());
     bot.set_my_commands(Command::bot_commands())
         .await
         .expect("Failed to set commands");
 
     let pool = db::create_pool().await.expect("Failed to create database pool");
 
+    log::info!("Canonicalizing repository URLs...");
+    if let Err(e) = updater::canonicalize_repository_urls(&pool).await {
+        log::error!("Repository URL canonicalization failed: {:?}", e);
+    }
+
     log::info!("Running initial database cleanup...");
     if let Err(e) = updater::cleanup_database(&pool).await {
         log::error!("Initial database cleanup failed: {:?}", e);
     }
 
     log::info!("Running initial repository update check...");
-    if let Err(e) = updater::check_for_updates(&bot, &pool).await {
+    if let Err(e) = updater::check_for_updates(&pool).await {
         log::error!("Initial repository update check failed: {:?}", e);
     }
 
-    tokio::spawn(updater::run_updater(bot.clone(), pool.clone()));
+    tokio::spawn(updater::run_updater(pool.clone()));
+    tokio::spawn(delivery::run_delivery_worker(bot.clone(), pool.clone()));
+    tokio::spawn(digest::run_digest_scheduler(pool.clone()));
 
-    let message_handler_chain = Update::filter_message()
+    if let Ok(addr) = std::env::var("WEBHOOK_ADDR") {
+        tokio::spawn(webhook::serve(addr, pool.clone()));
+    }
+
+    let message_handler_chain = dptree::entry()
         .enter_dialogue::<Message, InMemStorage<State>, State>()
         .branch(dptree::filter(|msg: Message| msg.text().map_or(false, |text| text == "/start")).endpoint(start_handler))
         .branch(dptree::entry().filter_command::<Command>().endpoint(command_handler))
@@ -65,7 +160,8 @@ async fn main() {
         .endpoint(callback_handler);
 
     let schema = dptree::entry()
-        .branch(message_handler_chain)
+        .branch(Update::filter_message().chain(message_handler_chain.clone()))
+        .branch(Update::filter_channel_post().chain(message_handler_chain))
         .branch(callback_handler_chain);
 
     Dispatcher::builder(bot, schema)
@@ -76,50 +172,124 @@ async fn main() {
         .await;
 }
 
-async fn start_handler(bot: Bot, dialogue: Dialogue, msg: Message, pool: DbPool) -> HandlerResult {
-    let user = msg.from().ok_or_else(|| anyhow!("Message has no sender"))?;
-    db::ensure_user_exists(&pool, user).await?;
+async fn start_handler(bot: AppBot, dialogue: Dialogue, msg: Message, pool: DbPool) -> HandlerResult {
+    db::ensure_chat_exists(&pool, &msg.chat).await?;
     dialogue.update(State::Start).await?;
-    bot.send_message(msg.chat.id, "👋 Welcome to GitNotify! Use the menu to manage your repository subscriptions.").await?;
+    reply_to(&bot, &msg, "👋 Welcome to GitNotify! Use the menu to manage your repository subscriptions.").await?;
     Ok(())
 }
 
-async fn command_handler(bot: Bot, dialogue: Dialogue, msg: Message, cmd: Command, pool: DbPool) -> HandlerResult {
-    let user = msg.from().ok_or_else(|| anyhow!("Message has no sender"))?;
-    db::ensure_user_exists(&pool, user).await?;
+async fn command_handler(bot: AppBot, dialogue: Dialogue, msg: Message, cmd: Command, pool: DbPool) -> HandlerResult {
+    db::ensure_chat_exists(&pool, &msg.chat).await?;
+    if !can_manage_subscriptions(&bot, &msg.chat, msg.from(), msg.sender_chat()).await? {
+        reply_to(&bot, &msg, ADMINS_ONLY).await?;
+        return Ok(());
+    }
+
     match cmd {
         Command::ListRepos => {
-            send_subscriptions_list(bot, msg.chat.id, None, &pool).await?;
+            send_subscriptions_list(bot, msg.chat.id, None, topic_thread_id(&msg), &pool).await?;
         }
-        Command::AddRepo => {
+        Command::AddRepo(urls) if urls.trim().is_empty() => {
             dialogue.update(State::ReceiveRepoUrl).await?;
-            bot.send_message(msg.chat.id, "🔗 Send me the repository URL (e.g., https://github.com/user/repo)")
+            send_prompt(&bot, &msg, "🔗 Send me the repository URL (e.g., https://github.com/user/repo)").await?;
+        }
+        Command::AddRepo(urls) => {
+            let urls: Vec<&str> = urls.split_whitespace().collect();
+            let status_msg = reply_to(&bot, &msg, "⏳ Checking repositories...").disable_web_page_preview(true).await?;
+
+            let mut summary = Vec::new();
+            for url in urls {
+                let line = match subscribe_repository(&pool, &msg, url).await {
+                    SubscribeOutcome::Subscribed => format!("✅ {}", url),
+                    SubscribeOutcome::Unreachable => format!("⚠️ {} is not accessible", url),
+                    SubscribeOutcome::Failed => format!("❌ {} could not be saved", url),
+                };
+                summary.push(line);
+            }
+
+            bot.edit_message_text(status_msg.chat.id, status_msg.id, format_summary(&summary))
                 .disable_web_page_preview(true)
                 .await?;
         }
+        Command::AddPrivate(_) if !credentials::is_configured() => {
+            reply_to(&bot, &msg, "🔐 Private repositories are not enabled on this bot.").await?;
+        }
+        Command::AddPrivate(_) if !msg.chat.is_private() => {
+            reply_to(&bot, &msg, "🔐 Credentials shouldn't be posted where others can read them, use /addprivate in a private chat with the bot.").await?;
+        }
+        Command::AddPrivate(url) if url.trim().is_empty() => {
+            dialogue.update(State::ReceivePrivateRepoUrl).await?;
+            send_prompt(&bot, &msg, "🔗 Send me the private repository URL").await?;
+        }
+        Command::AddPrivate(url) => {
+            let url = core::repo_url::canonicalize(&url);
+            dialogue.update(State::ReceiveCredentials { url }).await?;
+            send_prompt(&bot, &msg, CREDENTIALS_HELP).await?;
+        }
         Command::Toggle => {
-            let is_enabled = db::get_user_notification_status(&pool, msg.chat.id.0).await?;
+            let is_enabled = db::get_chat_notification_status(&pool, msg.chat.id.0).await?;
             let text = if is_enabled {
                 "Globally enabling all notifications."
             } else {
                 "Globally disabling all notifications."
             };
-            bot.send_message(msg.chat.id, text)
+            reply_to(&bot, &msg, text)
                 .reply_markup(global_notification_toggle_menu(is_enabled))
                 .await?;
         }
+        Command::Delivery => {
+            let preferences = db::get_delivery_preferences(&pool, msg.chat.id.0).await?;
+            reply_to(&bot, &msg, DELIVERY_HELP)
+                .reply_markup(delivery_settings_menu(&preferences))
+                .await?;
+        }
+        Command::Quiet => {
+            let preferences = db::get_delivery_preferences(&pool, msg.chat.id.0).await?;
+            reply_to(&bot, &msg, QUIET_HOURS_HELP)
+                .reply_markup(quiet_hours_menu(&preferences))
+                .await?;
+        }
+        Command::Export => {
+            let export = backup::export_subscriptions(&pool, msg.chat.id.0).await?;
+            let json = serde_json::to_vec_pretty(&export)?;
+            let mut request = bot.send_document(msg.chat.id, InputFile::memory(json).file_name("gitnotify-subscriptions.json"))
+                .caption(format!("📤 {} subscriptions exported.", export.repositories.len()));
+            if let Some(thread_id) = topic_thread_id(&msg) {
+                request = request.message_thread_id(thread_id);
+            }
+            request.await?;
+        }
+        Command::Import => {
+            dialogue.update(State::ReceiveImportFile).await?;
+            send_prompt(&bot, &msg, "📥 Send me a file created by /export.").await?;
+        }
     }
     Ok(())
 }
 
-async fn callback_handler(bot: Bot, _dialogue: Dialogue, q: CallbackQuery, pool: DbPool) -> HandlerResult {
-    db::ensure_user_exists(&pool, &q.from).await?;
+async fn callback_handler(bot: AppBot, dialogue: Dialogue, q: CallbackQuery, pool: DbPool) -> HandlerResult {
     let msg = q.message.ok_or_else(|| anyhow!("Callback query has no message"))?;
+    db::ensure_chat_exists(&pool, &msg.chat).await?;
+
+    if !can_manage_subscriptions(&bot, &msg.chat, Some(&q.from), None).await? {
+        bot.answer_callback_query(q.id).text(ADMINS_ONLY).show_alert(true).await?;
+        return Ok(());
+    }
 
     if let Some(data) = q.data {
+        // Clients can send any callback data, so a repository id is only acted on
+        // for repositories this chat subscribes to.
+        if let Some(repo_id) = callback_repository_id(&data) {
+            if !db::is_subscribed(&pool, msg.chat.id.0, repo_id).await? {
+                bot.answer_callback_query(q.id).text(NOT_SUBSCRIBED).show_alert(true).await?;
+                return Ok(());
+            }
+        }
+
         let result: Result<(), Box<dyn std::error::Error + Send + Sync>> = match data.as_str() {
             "list_repos" => {
-                send_subscriptions_list(bot.clone(), msg.chat.id, Some(msg.id), &pool).await?;
+                send_subscriptions_list(bot.clone(), msg.chat.id, Some(msg.id), None, &pool).await?;
                 Ok(())
             }
             _ if data.starts_with("view_repo_") => {
@@ -127,13 +297,13 @@ async fn callback_handler(bot: Bot, _dialogue: Dialogue, q: CallbackQuery, pool:
                 let repo = db::get_repository_by_id(&pool, repo_id).await?.ok_or_else(|| anyhow!("Repository not found"))?;
                 let refs = db::get_repository_refs(&pool, repo_id).await?;
 
-                let base_url = repo.url.trim_end_matches(".git");
-                let short_repo_name = base_url.split('/').rev().take(2).collect::<Vec<_>>().into_iter().rev().collect::<Vec<_>>().join("/");
+                let forge = Forge::detect(&repo.url);
+                let short_repo_name = updater::short_repo_name(forge.base_url());
 
-                let mut text = format!("📦 *Repository:* [{}]({})\n\n", escape(&short_repo_name), escape(base_url));
+                let mut text = format!("📦 *Repository:* [{}]({})\n\n", escape(&short_repo_name), escape(forge.base_url()));
                 text.push_str("*Tracked references:*\n");
 
-                let mut sorted_refs: Vec<_> = refs.into_iter().collect();
+                let mut sorted_refs: Vec<_> = refs.into_iter().filter(|(ref_name, _)| !TrackedRef::parse(ref_name).is_some_and(|tracked| tracked.is_review_merge())).collect();
                 sorted_refs.sort_by(|a, b| a.0.cmp(&b.0));
 
                 const MAX_REFS_DISPLAY: usize = 10;
@@ -148,22 +318,26 @@ async fn callback_handler(bot: Bot, _dialogue: Dialogue, q: CallbackQuery, pool:
                             break;
                         }
 
-                        let display_ref_name = ref_name.trim_start_matches("refs/heads/").trim_start_matches("refs/tags/").trim_start_matches("refs/pull/");
-                        let ref_link = if ref_name.starts_with("refs/heads/") {
-                            format!("{}/tree/{}", base_url, display_ref_name)
-                        } else if ref_name.starts_with("refs/tags/") {
-                            format!("{}/releases/tag/{}", base_url, display_ref_name)
-                        } else if ref_name.starts_with("refs/pull/") {
-                            format!("{}/pull/{}", base_url, display_ref_name)
-                        } else {
-                            base_url.to_string()
+                        let (display_ref_name, ref_link) = match TrackedRef::parse(ref_name) {
+                            Some(TrackedRef::Review(review)) => {
+                                let pr = review.to_pull_request(hash);
+                                (pr.display_id(), forge.review_url(&pr).unwrap_or_else(|| forge.base_url().to_string()))
+                            }
+                            Some(TrackedRef::Branch(name)) => (name.to_string(), forge.branch_url(name)),
+                            Some(TrackedRef::Tag(name)) => (name.to_string(), forge.tag_url(name)),
+                            None => (ref_name.clone(), forge.base_url().to_string()),
                         };
-                        let commit_link = format!("{}/commit/{}", base_url, hash);
-                        text.push_str(&format!("  • [{}]({}): [{}]({})\n", escape(display_ref_name), escape(&ref_link), &escape(&hash[..7]), escape(&commit_link)));
+                        let commit_link = forge.commit_url(hash);
+                        text.push_str(&format!("  • [{}]({}): [{}]({})\n", escape(&display_ref_name), escape(&ref_link), &escape(&hash[..7]), escape(&commit_link)));
                         displayed_count += 1;
                     }
                 }
 
+                if let Some(muted_until) = db::get_subscription_muted_until(&pool, msg.chat.id.0, repo_id).await? {
+                    let timezone = db::get_delivery_preferences(&pool, msg.chat.id.0).await?.timezone;
+                    text.push_str(&format!("\n🔕 _Snoozed until {}_", escape(&format_local_time(muted_until, &timezone))));
+                }
+
                 bot.edit_message_text(msg.chat.id, msg.id, text)
                     .disable_web_page_preview(true)
                     .reply_markup(repository_menu(repo_id))
@@ -174,7 +348,14 @@ async fn callback_handler(bot: Bot, _dialogue: Dialogue, q: CallbackQuery, pool:
             _ if data.starts_with("unsubscribe_") => {
                 let repo_id: i32 = data.trim_start_matches("unsubscribe_").parse()?;
                 db::remove_repository_subscription(&pool, msg.chat.id.0, repo_id).await?;
-                send_subscriptions_list(bot.clone(), msg.chat.id, Some(msg.id), &pool).await?;
+                send_subscriptions_list(bot.clone(), msg.chat.id, Some(msg.id), None, &pool).await?;
+                Ok(())
+            }
+            _ if data.starts_with("keep_trying_") => {
+                let repo_id: i32 = data.trim_start_matches("keep_trying_").parse()?;
+                db::extend_repository_grace(&pool, repo_id).await?;
+                bot.edit_message_text(msg.chat.id, msg.id, "🔁 OK, GitNotify will keep checking this repository for all of its subscribers before giving up on it.")
+                    .await?;
                 Ok(())
             }
             _ if data.starts_with("repo_settings_") => {
@@ -195,9 +376,16 @@ async fn callback_handler(bot: Bot, _dialogue: Dialogue, q: CallbackQuery, pool:
                 match setting_name.as_str() {
                     "new_branch" => settings.notify_on_new_branch = !settings.notify_on_new_branch,
                     "new_tag" => settings.notify_on_new_tag = !settings.notify_on_new_tag,
+                    "release_filter" => settings.release_filter = settings.release_filter.next(),
+                    "tag_changelog" => settings.include_tag_changelog = !settings.include_tag_changelog,
                     "branch_update" => settings.notify_on_branch_update = !settings.notify_on_branch_update,
+                    "force_push" => settings.notify_on_force_push = !settings.notify_on_force_push,
                     "new_pr" => settings.notify_on_new_pr = !settings.notify_on_new_pr,
                     "pr_update" => settings.notify_on_pr_update = !settings.notify_on_pr_update,
+                    "branch_delete" => settings.notify_on_branch_delete = !settings.notify_on_branch_delete,
+                    "tag_delete" => settings.notify_on_tag_delete = !settings.notify_on_tag_delete,
+                    "pr_close" => settings.notify_on_pr_close = !settings.notify_on_pr_close,
+                    "delivery_mode" => settings.delivery_mode = DeliveryMode::next_override(settings.delivery_mode),
                     _ => log::warn!("Unknown setting name: {}", setting_name),
                 }
 
@@ -209,10 +397,186 @@ async fn callback_handler(bot: Bot, _dialogue: Dialogue, q: CallbackQuery, pool:
                     .await?;
                 Ok(())
             }
+            _ if data.starts_with("repo_snooze_") => {
+                let repo_id: i32 = data.trim_start_matches("repo_snooze_").parse()?;
+                let muted_until = db::get_subscription_muted_until(&pool, msg.chat.id.0, repo_id).await?;
+                bot.edit_message_text(msg.chat.id, msg.id, "🔕 Mute this repository for a while:")
+                    .reply_markup(snooze_menu(repo_id, muted_until.is_some()))
+                    .await?;
+                Ok(())
+            }
+            _ if data.starts_with("snooze_") => {
+                let parts: Vec<&str> = data.trim_start_matches("snooze_").split('_').collect();
+                let repo_id: i32 = parts[0].parse()?;
+                let duration = match parts[1] {
+                    "8h" => Some(Duration::hours(8)),
+                    "1d" => Some(Duration::days(1)),
+                    "1w" => Some(Duration::weeks(1)),
+                    "off" => None,
+                    other => return Err(anyhow!("Unknown snooze duration: {}", other).into()),
+                };
+                let muted_until = duration.map(|duration| Utc::now() + duration);
+                db::set_subscription_muted_until(&pool, msg.chat.id.0, repo_id, muted_until).await?;
+
+                let text = match muted_until {
+                    Some(muted_until) => {
+                        let timezone = db::get_delivery_preferences(&pool, msg.chat.id.0).await?.timezone;
+                        format!("🔕 Snoozed until {}.", format_local_time(muted_until, &timezone))
+                    }
+                    None => "🔔 Notifications for this repository are back on.".to_string(),
+                };
+                bot.edit_message_text(msg.chat.id, msg.id, text)
+                    .reply_markup(repository_menu(repo_id))
+                    .await?;
+                Ok(())
+            }
+            _ if data.starts_with("repo_topics_") => {
+                let repo_id: i32 = data.trim_start_matches("repo_topics_").parse()?;
+                let topics = db::get_subscription_topics(&pool, msg.chat.id.0, repo_id).await?;
+                bot.edit_message_text(msg.chat.id, msg.id, describe_topic_routes(&topics))
+                    .reply_markup(topic_routing_menu(repo_id))
+                    .await?;
+                Ok(())
+            }
+            _ if data.starts_with("route_here_") => {
+                let parts: Vec<&str> = data.trim_start_matches("route_here_").split('_').collect();
+                let repo_id: i32 = parts[0].parse()?;
+                let category = match parts[1] {
+                    "all" => None,
+                    kind => Some(EventCategory::from_db(kind).ok_or_else(|| anyhow!("Unknown event category: {}", kind))?),
+                };
+                db::set_subscription_topic(&pool, msg.chat.id.0, repo_id, category, topic_thread_id(&msg)).await?;
+
+                let topics = db::get_subscription_topics(&pool, msg.chat.id.0, repo_id).await?;
+                bot.edit_message_text(msg.chat.id, msg.id, describe_topic_routes(&topics))
+                    .reply_markup(topic_routing_menu(repo_id))
+                    .await?;
+                Ok(())
+            }
+            _ if data.starts_with("route_reset_") => {
+                let repo_id: i32 = data.trim_start_matches("route_reset_").parse()?;
+                db::reset_subscription_topics(&pool, msg.chat.id.0, repo_id).await?;
+
+                let topics = db::get_subscription_topics(&pool, msg.chat.id.0, repo_id).await?;
+                bot.edit_message_text(msg.chat.id, msg.id, describe_topic_routes(&topics))
+                    .reply_markup(topic_routing_menu(repo_id))
+                    .await?;
+                Ok(())
+            }
+            _ if data.starts_with("repo_filters_") => {
+                let repo_id: i32 = data.trim_start_matches("repo_filters_").parse()?;
+                let filters = db::get_subscription_filters(&pool, msg.chat.id.0, repo_id).await?;
+                bot.edit_message_text(msg.chat.id, msg.id, FILTERS_HELP)
+                    .reply_markup(filters_menu(repo_id, &filters))
+                    .await?;
+                Ok(())
+            }
+            _ if data.starts_with("add_filter_") => {
+                let parts: Vec<&str> = data.trim_start_matches("add_filter_").split('_').collect();
+                let repo_id: i32 = parts[0].parse()?;
+                let ref_kind = RefKind::from_db(parts[1]).ok_or_else(|| anyhow!("Unknown ref kind: {}", parts[1]))?;
+                let mode = FilterMode::from_db(parts[2]).ok_or_else(|| anyhow!("Unknown filter mode: {}", parts[2]))?;
+
+                dialogue.update(State::ReceiveFilterPattern { repo_id, ref_kind, mode }).await?;
+                let target = match ref_kind {
+                    RefKind::Branch => "branches",
+                    RefKind::Tag => "tags",
+                };
+                send_prompt(&bot, &msg, format!("✏️ Send me the pattern for {} to {} (e.g. release/* or re:v[0-9]+\\..*)", target, mode.as_str())).await?;
+                Ok(())
+            }
+            _ if data.starts_with("remove_filter_") => {
+                let parts: Vec<&str> = data.trim_start_matches("remove_filter_").split('_').collect();
+                let repo_id: i32 = parts[0].parse()?;
+                let filter_id: i32 = parts[1].parse()?;
+                db::remove_subscription_filter(&pool, msg.chat.id.0, filter_id).await?;
+                let filters = db::get_subscription_filters(&pool, msg.chat.id.0, repo_id).await?;
+                bot.edit_message_text(msg.chat.id, msg.id, FILTERS_HELP)
+                    .reply_markup(filters_menu(repo_id, &filters))
+                    .await?;
+                Ok(())
+            }
+            _ if data.starts_with("repo_webhook_") || data.starts_with("webhook_regen_") => {
+                let regenerate = data.starts_with("webhook_regen_");
+                let repo_id: i32 = data.trim_start_matches("repo_webhook_").trim_start_matches("webhook_regen_").parse()?;
+
+                match std::env::var("WEBHOOK_BASE_URL") {
+                    Ok(base_url) => {
+                        let secret = match db::get_webhook_secret(&pool, msg.chat.id.0, repo_id).await? {
+                            Some(secret) if !regenerate => secret,
+                            _ => {
+                                let secret = webhook::generate_secret();
+                                db::set_webhook_secret(&pool, msg.chat.id.0, repo_id, &secret).await?;
+                                secret
+                            }
+                        };
+
+                        let text = format!(
+                            "🪝 Webhook for this repository\n\n\
+                             Payload URL: {}/webhook/{}/{}\n\
+                             Content type: application/json\n\
+                             Secret: {}\n\n\
+                             Send push and pull request events (GitHub, Gitea) or push, tag push and merge request events (GitLab, with the secret as token). \
+                             The secret belongs to this chat, and every delivery is checked against the repository before anything is announced. \
+                             Polling pauses while deliveries arrive and resumes after {} minutes without one.",
+                            base_url.trim_end_matches('/'),
+                            repo_id,
+                            msg.chat.id.0,
+                            secret,
+                            config::env_or("WEBHOOK_STALE_MINS", 60u64)
+                        );
+                        bot.edit_message_text(msg.chat.id, msg.id, text)
+                            .disable_web_page_preview(true)
+                            .reply_markup(webhook_menu(repo_id))
+                            .await?;
+                    }
+                    Err(_) => {
+                        bot.edit_message_text(msg.chat.id, msg.id, "🪝 Webhook delivery is not configured on this bot, the repository is checked by polling.")
+                            .reply_markup(repository_menu(repo_id))
+                            .await?;
+                    }
+                }
+                Ok(())
+            }
+            _ if data.starts_with("set_delivery_") => {
+                let mode = DeliveryMode::from_db(data.trim_start_matches("set_delivery_"))
+                    .filter(|mode| *mode != DeliveryMode::AfterQuietHours)
+                    .ok_or_else(|| anyhow!("Unknown delivery mode: {}", data))?;
+                db::set_chat_delivery_mode(&pool, msg.chat.id.0, mode).await?;
+                let preferences = db::get_delivery_preferences(&pool, msg.chat.id.0).await?;
+                bot.edit_message_text(msg.chat.id, msg.id, DELIVERY_HELP)
+                    .reply_markup(delivery_settings_menu(&preferences))
+                    .await?;
+                Ok(())
+            }
+            "set_digest_time" => {
+                dialogue.update(State::ReceiveDigestTime).await?;
+                send_prompt(&bot, &msg, "🕘 Send me the time for your daily digest as HH:MM (e.g. 09:00).").await?;
+                Ok(())
+            }
+            "set_timezone" => {
+                dialogue.update(State::ReceiveTimezone).await?;
+                send_prompt(&bot, &msg, "🌍 Send me your timezone (e.g. Europe/Berlin or UTC).").await?;
+                Ok(())
+            }
+            "set_quiet_hours" => {
+                dialogue.update(State::ReceiveQuietHours).await?;
+                send_prompt(&bot, &msg, "🌙 Send me your quiet hours as HH:MM-HH:MM (e.g. 22:00-07:00), or \"off\" to disable them.").await?;
+                Ok(())
+            }
+            "toggle_quiet_mode" => {
+                let preferences = db::get_delivery_preferences(&pool, msg.chat.id.0).await?;
+                db::set_chat_quiet_hours_mode(&pool, msg.chat.id.0, preferences.quiet_hours_mode.next()).await?;
+                let preferences = db::get_delivery_preferences(&pool, msg.chat.id.0).await?;
+                bot.edit_message_text(msg.chat.id, msg.id, QUIET_HOURS_HELP)
+                    .reply_markup(quiet_hours_menu(&preferences))
+                    .await?;
+                Ok(())
+            }
             "toggle_global_notifications" => {
-                let current_status = db::get_user_notification_status(&pool, msg.chat.id.0).await?;
+                let current_status = db::get_chat_notification_status(&pool, msg.chat.id.0).await?;
                 let new_status = !current_status;
-                db::set_user_notification_status(&pool, msg.chat.id.0, new_status).await?;
+                db::set_chat_notification_status(&pool, msg.chat.id.0, new_status).await?;
                 let text = if new_status {
                     "✅ All notifications have been enabled."
                 } else {
@@ -245,45 +609,341 @@ async fn callback_handler(bot: Bot, _dialogue: Dialogue, q: CallbackQuery, pool:
     Ok(())
 }
 
-async fn message_handler(bot: Bot, dialogue: Dialogue, msg: Message, pool: DbPool) -> HandlerResult {
-    let user = msg.from().ok_or_else(|| anyhow!("Message has no sender"))?;
-    db::ensure_user_exists(&pool, user).await?;
+fn callback_repository_id(data: &str) -> Option<i32> {
+    let rest = REPOSITORY_CALLBACKS.iter().find_map(|prefix| data.strip_prefix(prefix))?;
+    rest.split('_').next()?.parse().ok()
+}
+
+async fn message_handler(bot: AppBot, dialogue: Dialogue, msg: Message, pool: DbPool) -> HandlerResult {
+    db::ensure_chat_exists(&pool, &msg.chat).await?;
     let state = dialogue.get().await?.unwrap_or_default();
 
+    // Group members chat freely, only answers from administrators continue a dialogue.
+    if !msg.chat.is_private() && (matches!(state, State::Start) || !can_manage_subscriptions(&bot, &msg.chat, msg.from(), msg.sender_chat()).await?) {
+        return Ok(());
+    }
+
     match state {
         State::ReceiveRepoUrl => {
-            let url = msg.text().ok_or_else(|| anyhow!("Message has no text"))?;
-            let status_msg = bot.send_message(msg.chat.id, "⏳ Checking repository...").disable_web_page_preview(true).await?;
+            let url = msg.text().ok_or_else(|| anyhow!("Message has no text"))?.trim();
+            let status_msg = reply_to(&bot, &msg, "⏳ Checking repository...").disable_web_page_preview(true).await?;
             dialogue.update(State::Start).await?;
 
-            match core::git_service::ls_remote(url).await {
-                Ok(_) => {
-                    match db::add_repository_subscription(&pool, user, url).await {
-                        Ok(_) => {
-                            bot.edit_message_text(status_msg.chat.id, status_msg.id, "✅ Successfully subscribed to the repository!").await?;
-                        }
-                        Err(e) => {
-                            log::error!("Database error: {:?}", e);
-                            bot.edit_message_text(status_msg.chat.id, status_msg.id, "❌ An internal error occurred while subscribing.").await?;
-                        }
-                    }
-                }
+            let text = match subscribe_repository(&pool, &msg, url).await {
+                SubscribeOutcome::Subscribed => "✅ Successfully subscribed to the repository!",
+                SubscribeOutcome::Unreachable => "⚠️ Could not access the repository. Please check the URL and try again, or use /addprivate if the repository is private.",
+                SubscribeOutcome::Failed => "❌ An internal error occurred while subscribing.",
+            };
+            bot.edit_message_text(status_msg.chat.id, status_msg.id, text).await?;
+        }
+        State::ReceivePrivateRepoUrl => {
+            let url = msg.text().ok_or_else(|| anyhow!("Message has no text"))?.trim();
+            let url = core::repo_url::canonicalize(url);
+            dialogue.update(State::ReceiveCredentials { url }).await?;
+            send_prompt(&bot, &msg, CREDENTIALS_HELP).await?;
+        }
+        State::ReceiveCredentials { url } => {
+            let text = msg.text().unwrap_or_default().to_string();
+            dialogue.update(State::Start).await?;
+            if let Err(e) = bot.delete_message(msg.chat.id, msg.id).await {
+                log::warn!("Failed to delete credentials message in {}: {:?}", msg.chat.id, e);
+            }
+
+            let Some(credentials) = Credentials::parse(&text) else {
+                reply_to(&bot, &msg, "⚠️ These don't look like an access token or a private SSH key.").await?;
+                return Ok(());
+            };
+
+            let status_msg = reply_to(&bot, &msg, "⏳ Checking repository...").disable_web_page_preview(true).await?;
+            let text = match subscribe_private_repository(&pool, &msg, &url, &credentials).await {
+                PrivateSubscribeOutcome::Subscribed => format!("✅ Subscribed to the private repository using your {}.", credentials.describe()),
+                PrivateSubscribeOutcome::Public => "✅ Subscribed. The repository is public, so your credentials were not stored.".to_string(),
+                PrivateSubscribeOutcome::Rejected => format!("⚠️ Could not access the repository with this {}. Please check the URL and its permissions.", credentials.describe()),
+                PrivateSubscribeOutcome::Failed => "❌ An internal error occurred while subscribing.".to_string(),
+            };
+            bot.edit_message_text(status_msg.chat.id, status_msg.id, text).await?;
+        }
+        State::ReceiveFilterPattern { repo_id, ref_kind, mode } => {
+            let pattern = msg.text().ok_or_else(|| anyhow!("Message has no text"))?.trim();
+            dialogue.update(State::Start).await?;
+
+            if let Err(e) = filters::compile_pattern(pattern) {
+                reply_to(&bot, &msg, format!("⚠️ Invalid pattern: {}", e)).await?;
+                return Ok(());
+            }
+
+            db::add_subscription_filter(&pool, msg.chat.id.0, repo_id, ref_kind, mode, pattern).await?;
+            let filters = db::get_subscription_filters(&pool, msg.chat.id.0, repo_id).await?;
+            reply_to(&bot, &msg, FILTERS_HELP)
+                .reply_markup(filters_menu(repo_id, &filters))
+                .await?;
+        }
+        State::ReceiveDigestTime => {
+            let text = msg.text().ok_or_else(|| anyhow!("Message has no text"))?.trim();
+            dialogue.update(State::Start).await?;
+
+            let Ok(digest_time) = NaiveTime::parse_from_str(text, "%H:%M") else {
+                reply_to(&bot, &msg, "⚠️ Invalid time, please use the HH:MM format.").await?;
+                return Ok(());
+            };
+
+            db::set_chat_digest_time(&pool, msg.chat.id.0, digest_time).await?;
+            let preferences = db::get_delivery_preferences(&pool, msg.chat.id.0).await?;
+            reply_to(&bot, &msg, DELIVERY_HELP)
+                .reply_markup(delivery_settings_menu(&preferences))
+                .await?;
+        }
+        State::ReceiveTimezone => {
+            let text = msg.text().ok_or_else(|| anyhow!("Message has no text"))?.trim();
+            dialogue.update(State::Start).await?;
+
+            let Ok(timezone) = text.parse::<Tz>() else {
+                reply_to(&bot, &msg, "⚠️ Unknown timezone, please use a name like Europe/Berlin.").await?;
+                return Ok(());
+            };
+
+            db::set_chat_timezone(&pool, msg.chat.id.0, timezone.name()).await?;
+            let preferences = db::get_delivery_preferences(&pool, msg.chat.id.0).await?;
+            reply_to(&bot, &msg, DELIVERY_HELP)
+                .reply_markup(delivery_settings_menu(&preferences))
+                .await?;
+        }
+        State::ReceiveQuietHours => {
+            let text = msg.text().ok_or_else(|| anyhow!("Message has no text"))?.trim();
+            dialogue.update(State::Start).await?;
+
+            let window = if text.eq_ignore_ascii_case("off") {
+                None
+            } else {
+                let Some(window) = QuietHours::parse_window(text) else {
+                    reply_to(&bot, &msg, "⚠️ Invalid quiet hours, please use the HH:MM-HH:MM format.").await?;
+                    return Ok(());
+                };
+                Some(window)
+            };
+
+            db::set_chat_quiet_hours(&pool, msg.chat.id.0, window).await?;
+            let preferences = db::get_delivery_preferences(&pool, msg.chat.id.0).await?;
+            reply_to(&bot, &msg, QUIET_HOURS_HELP)
+                .reply_markup(quiet_hours_menu(&preferences))
+                .await?;
+        }
+        State::ReceiveImportFile => {
+            dialogue.update(State::Start).await?;
+
+            let Some(document) = msg.document() else {
+                reply_to(&bot, &msg, "⚠️ Please send the exported file as a document.").await?;
+                return Ok(());
+            };
+            if document.file.size > MAX_IMPORT_FILE_SIZE {
+                reply_to(&bot, &msg, "⚠️ This file is too large to be a GitNotify export.").await?;
+                return Ok(());
+            }
+
+            let file = bot.get_file(&document.file.id).await?;
+            let mut contents = Vec::new();
+            bot.inner().download_file(&file.path, &mut contents).await?;
+
+            let export: SubscriptionExport = match serde_json::from_slice(&contents) {
+                Ok(export) => export,
                 Err(e) => {
-                    log::warn!("Failed to ls_remote for {}: {:?}", url, e);
-                    bot.edit_message_text(status_msg.chat.id, status_msg.id, "⚠️ Could not access the repository. Please check the URL and ensure the repository is public, then try again.").await?;
+                    reply_to(&bot, &msg, format!("⚠️ This is not a GitNotify export: {}", e)).await?;
+                    return Ok(());
+                }
+            };
+            if export.version > backup::EXPORT_VERSION {
+                reply_to(&bot, &msg, "⚠️ This export was created by a newer version of GitNotify.").await?;
+                return Ok(());
+            }
+
+            let total = export.repositories.len();
+            let status_msg = reply_to(&bot, &msg, format!("⏳ Importing {} repositories...", total)).await?;
+
+            // Every repository is checked with ls-remote, a few at a time so a large
+            // export doesn't take minutes while the status message shows the progress.
+            let limit = Arc::new(Semaphore::new(MAX_CONCURRENT_IMPORTS));
+            let mut tasks = JoinSet::new();
+            for (index, repository) in export.repositories.into_iter().enumerate() {
+                let (pool, chat, limit) = (pool.clone(), msg.chat.clone(), limit.clone());
+                let thread_id = topic_thread_id(&msg);
+                tasks.spawn(async move {
+                    let _permit = limit.acquire_owned().await;
+                    let outcome = backup::import_repository(&pool, &chat, thread_id, &repository).await;
+                    (index, repository.url, outcome)
+                });
+            }
+
+            let mut results = Vec::with_capacity(total);
+            let mut last_progress = Instant::now();
+            while let Some(result) = tasks.join_next().await {
+                results.push(result?);
+                if results.len() < total && last_progress.elapsed() >= IMPORT_PROGRESS_INTERVAL {
+                    last_progress = Instant::now();
+                    let progress = format!("⏳ Importing repositories... {}/{}", results.len(), total);
+                    if let Err(e) = bot.edit_message_text(status_msg.chat.id, status_msg.id, progress).await {
+                        log::warn!("Failed to update import progress: {:?}", e);
+                    }
                 }
             }
+            results.sort_by_key(|(index, _, _)| *index);
+
+            let mut summary: Vec<String> = results
+                .into_iter()
+                .map(|(_, url, outcome)| match outcome {
+                    ImportOutcome::Imported => format!("✅ {}", url),
+                    ImportOutcome::Invalid(reason) => format!("⚠️ {} skipped: {}", url, reason),
+                    ImportOutcome::Unreachable => format!("⚠️ {} is not accessible", url),
+                    ImportOutcome::Failed => format!("❌ {} could not be saved", url),
+                })
+                .collect();
+            if summary.is_empty() {
+                summary.push("ℹ️ The export contains no repositories.".to_string());
+            }
+
+            bot.edit_message_text(status_msg.chat.id, status_msg.id, format_summary(&summary))
+                .disable_web_page_preview(true)
+                .await?;
         }
         State::Start => {
-            bot.send_message(msg.chat.id, "ℹ️ Please use the menu commands.").await?;
+            reply_to(&bot, &msg, "ℹ️ Please use the menu commands.").await?;
         }
     }
 
     Ok(())
 }
 
-async fn send_subscriptions_list(bot: Bot, chat_id: ChatId, message_id: Option<MessageId>, pool: &DbPool) -> HandlerResult {
-    let subscriptions = db::get_user_subscriptions(pool, chat_id.0).await?;
+fn format_summary(lines: &[String]) -> String {
+    const MAX_SUMMARY_LENGTH: usize = 4000;
+    let mut text = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        if text.len() + line.len() > MAX_SUMMARY_LENGTH {
+            text.push_str(&format!("…and {} more", lines.len() - index));
+            break;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
+
+enum SubscribeOutcome {
+    Subscribed,
+    Unreachable,
+    Failed,
+}
+
+async fn subscribe_repository(pool: &DbPool, msg: &Message, url: &str) -> SubscribeOutcome {
+    let url = core::repo_url::canonicalize(url);
+    if let Err(e) = core::git_service::ls_remote(&url, None).await {
+        log::warn!("Failed to ls_remote for {}: {:?}", url, e);
+        return SubscribeOutcome::Unreachable;
+    }
+
+    match db::add_repository_subscription(pool, &msg.chat, topic_thread_id(msg), &url).await {
+        Ok(_) => SubscribeOutcome::Subscribed,
+        Err(e) => {
+            log::error!("Database error: {:?}", e);
+            SubscribeOutcome::Failed
+        }
+    }
+}
+
+enum PrivateSubscribeOutcome {
+    Subscribed,
+    Public,
+    Rejected,
+    Failed,
+}
+
+// Credentials are only kept when the repository really needs them, otherwise
+// attaching a token would hide a public repository from its other subscribers.
+async fn subscribe_private_repository(pool: &DbPool, msg: &Message, url: &str, credentials: &Credentials) -> PrivateSubscribeOutcome {
+    if core::git_service::ls_remote(url, None).await.is_ok() {
+        return match db::add_repository_subscription(pool, &msg.chat, topic_thread_id(msg), url).await {
+            Ok(_) => PrivateSubscribeOutcome::Public,
+            Err(e) => {
+                log::error!("Database error: {:?}", e);
+                PrivateSubscribeOutcome::Failed
+            }
+        };
+    }
+
+    if let Err(e) = core::git_service::ls_remote(url, Some(credentials)).await {
+        log::warn!("Failed to ls_remote for {} with credentials: {:?}", url, e);
+        return PrivateSubscribeOutcome::Rejected;
+    }
+
+    let sealed = match credentials.encrypt() {
+        Ok(sealed) => sealed,
+        Err(e) => {
+            log::error!("Failed to encrypt credentials: {}", e);
+            return PrivateSubscribeOutcome::Failed;
+        }
+    };
+    let result = async {
+        let repo_id = db::add_repository_subscription(pool, &msg.chat, topic_thread_id(msg), url).await?;
+        db::set_subscription_credentials(pool, msg.chat.id.0, repo_id, &sealed).await
+    }
+    .await;
+    match result {
+        Ok(()) => PrivateSubscribeOutcome::Subscribed,
+        Err(e) => {
+            log::error!("Database error: {:?}", e);
+            PrivateSubscribeOutcome::Failed
+        }
+    }
+}
+
+fn describe_topic_routes(topics: &TopicRoutes) -> String {
+    let describe = |thread_id: Option<i32>| match thread_id {
+        Some(thread_id) => format!("topic #{}", thread_id),
+        None => "General".to_string(),
+    };
+
+    let mut text = format!(
+        "🧵 Open this menu inside a forum topic to route notifications there.\n\nAll events: {}",
+        describe(topics.default)
+    );
+    for category in [EventCategory::Branch, EventCategory::Tag, EventCategory::PullRequest] {
+        if let Some(thread_id) = topics.get(category) {
+            text.push_str(&format!("\n{}: {}", category.label(), describe(Some(thread_id))));
+        }
+    }
+    text
+}
+
+fn topic_thread_id(msg: &Message) -> Option<i32> {
+    match &msg.kind {
+        MessageKind::Common(common) if common.is_topic_message => msg.thread_id,
+        _ => None,
+    }
+}
+
+// Answers land in the forum topic the message came from instead of the General topic.
+fn reply_to(bot: &AppBot, msg: &Message, text: impl Into<String>) -> <AppBot as Requester>::SendMessage {
+    let request = bot.send_message(msg.chat.id, text);
+    match topic_thread_id(msg) {
+        Some(thread_id) => request.message_thread_id(thread_id),
+        None => request,
+    }
+}
+
+// Groups only forward replies to the bot in privacy mode, so prompts there ask for one.
+async fn send_prompt(bot: &AppBot, msg: &Message, text: impl Into<String>) -> Result<Message, RequestError> {
+    let mut request = reply_to(bot, msg, text).disable_web_page_preview(true);
+    if msg.chat.is_group() || msg.chat.is_supergroup() {
+        request = request.reply_markup(ForceReply::new().selective(true));
+    }
+    request.await
+}
+
+fn format_local_time(at: DateTime<Utc>, timezone: &str) -> String {
+    let tz: Tz = timezone.parse().unwrap_or(Tz::UTC);
+    at.with_timezone(&tz).format("%Y-%m-%d %H:%M %Z").to_string()
+}
+
+async fn send_subscriptions_list(bot: AppBot, chat_id: ChatId, message_id: Option<MessageId>, thread_id: Option<i32>, pool: &DbPool) -> HandlerResult {
+    let subscriptions = db::get_chat_subscriptions(pool, chat_id.0).await?;
     let text = if subscriptions.is_empty() {
         "📚 You have no active subscriptions."
     } else {
@@ -297,10 +957,13 @@ async fn send_subscriptions_list(bot: Bot, chat_id: ChatId, message_id: Option<M
             .reply_markup(markup)
             .await?;
     } else {
-        bot.send_message(chat_id, text)
+        let mut request = bot.send_message(chat_id, text)
             .disable_web_page_preview(true)
-            .reply_markup(markup)
-            .await?;
+            .reply_markup(markup);
+        if let Some(thread_id) = thread_id {
+            request = request.message_thread_id(thread_id);
+        }
+        request.await?;
     }
     Ok(())
 }
@@ -0,0 +1,96 @@
+mod payloads;
+mod signature;
+
+use crate::core::updater;
+use crate::infrastructure::db::{self, DbPool};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use payloads::WebhookSource;
+use rand::distributions::{Alphanumeric, DistString};
+
+#[derive(Clone)]
+struct WebhookState {
+    pool: DbPool,
+}
+
+pub fn generate_secret() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 40)
+}
+
+pub async fn serve(addr: String, pool: DbPool) {
+    let app = Router::new()
+        .route("/webhook/:repo_id/:chat_id", post(handle_webhook))
+        .with_state(WebhookState { pool });
+
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Failed to bind webhook server to {}: {:?}", addr, e);
+            return;
+        }
+    };
+
+    log::info!("Webhook server listening on {}", addr);
+    if let Err(e) = axum::serve(listener, app).await {
+        log::error!("Webhook server stopped: {:?}", e);
+    }
+}
+
+async fn handle_webhook(
+    State(state): State<WebhookState>,
+    Path((repo_id, chat_id)): Path<(i32, i64)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let repo = match db::get_repository_by_id(&state.pool, repo_id).await {
+        Ok(Some(repo)) => repo,
+        Ok(None) => return StatusCode::NOT_FOUND,
+        Err(e) => {
+            log::error!("Failed to load repository {} for webhook: {:?}", repo_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    let secret = match db::get_webhook_secret(&state.pool, chat_id, repo_id).await {
+        Ok(Some(secret)) => secret,
+        Ok(None) => return StatusCode::NOT_FOUND,
+        Err(e) => {
+            log::error!("Failed to load webhook secret for {}: {:?}", repo.url, e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    let Some((source, event_name)) = WebhookSource::detect(&headers) else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    if !signature::verify(source, &headers, &secret, &body) {
+        log::warn!("Rejected webhook for {} with an invalid signature", repo.url);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let events = match payloads::parse(source, &event_name, &body) {
+        Ok(events) => events,
+        Err(e) => {
+            log::warn!("Failed to parse {} webhook for {}: {:?}", event_name, repo.url, e);
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    // Events are committed to the outbox before the forge gets its answer, a crash
+    // in between makes the forge retry instead of losing the delivery.
+    if !events.is_empty() {
+        if let Err(e) = updater::process_webhook_events(&state.pool, &repo, events).await {
+            log::error!("Failed to process webhook events for {}: {:?}", repo.url, e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+
+    if let Err(e) = db::record_webhook_delivery(&state.pool, chat_id, repo_id).await {
+        log::error!("Failed to record webhook delivery for {}: {:?}", repo.url, e);
+    }
+    StatusCode::OK
+}
@@ -0,0 +1,284 @@
+use crate::core::events::{Branch, GitEvent, PullRequest, ReviewKind, Tag, TrackedRef};
+use axum::http::HeaderMap;
+use serde::Deserialize;
+
+const ZERO_SHA: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookSource {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+#[derive(Deserialize)]
+struct PushPayload {
+    #[serde(rename = "ref")]
+    ref_name: String,
+    before: String,
+    after: String,
+}
+
+#[derive(Deserialize)]
+struct PullRequestPayload {
+    action: String,
+    number: u64,
+    pull_request: PullRequestObject,
+}
+
+#[derive(Deserialize)]
+struct PullRequestObject {
+    head: PullRequestHead,
+}
+
+#[derive(Deserialize)]
+struct PullRequestHead {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestPayload {
+    object_attributes: MergeRequestAttributes,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestAttributes {
+    iid: u64,
+    action: Option<String>,
+    oldrev: Option<String>,
+    last_commit: MergeRequestCommit,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestCommit {
+    id: String,
+}
+
+impl WebhookSource {
+    // Gitea also sends X-GitHub-Event for compatibility, so it has to be checked first.
+    pub fn detect(headers: &HeaderMap) -> Option<(WebhookSource, String)> {
+        let candidates = [
+            ("X-Gitea-Event", WebhookSource::Gitea),
+            ("X-Gitlab-Event", WebhookSource::GitLab),
+            ("X-GitHub-Event", WebhookSource::GitHub),
+        ];
+        candidates.into_iter().find_map(|(header, source)| {
+            headers
+                .get(header)
+                .and_then(|value| value.to_str().ok())
+                .map(|event| (source, event.to_string()))
+        })
+    }
+}
+
+pub fn parse(
+    source: WebhookSource,
+    event_name: &str,
+    body: &[u8],
+) -> Result<Vec<GitEvent>, serde_json::Error> {
+    match (source, event_name) {
+        (WebhookSource::GitHub | WebhookSource::Gitea, "push")
+        | (WebhookSource::GitLab, "Push Hook" | "Tag Push Hook") => {
+            Ok(push_events(serde_json::from_slice(body)?))
+        }
+        (WebhookSource::GitHub | WebhookSource::Gitea, "pull_request") => {
+            Ok(pull_request_events(serde_json::from_slice(body)?))
+        }
+        (WebhookSource::GitLab, "Merge Request Hook") => {
+            Ok(merge_request_events(serde_json::from_slice(body)?))
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn push_events(push: PushPayload) -> Vec<GitEvent> {
+    let created = push.before == ZERO_SHA;
+    let deleted = push.after == ZERO_SHA;
+
+    let event = match TrackedRef::parse(&push.ref_name) {
+        Some(TrackedRef::Branch(_)) if deleted => Some(GitEvent::BranchDeleted(Branch {
+            name: push.ref_name,
+            sha: push.before,
+        })),
+        Some(TrackedRef::Branch(_)) if created => Some(GitEvent::NewBranch(Branch {
+            name: push.ref_name,
+            sha: push.after,
+        })),
+        Some(TrackedRef::Branch(_)) => Some(GitEvent::BranchUpdated {
+            name: push.ref_name,
+            old_sha: push.before,
+            new_sha: push.after,
+            commits: Vec::new(),
+            commit_count: 0,
+            history_unknown: false,
+        }),
+        Some(TrackedRef::Tag(_)) if deleted => Some(GitEvent::TagDeleted(Tag {
+            name: push.ref_name,
+            sha: push.before,
+            ..Default::default()
+        })),
+        Some(TrackedRef::Tag(_)) if created => Some(GitEvent::NewTag(Tag {
+            name: push.ref_name,
+            sha: push.after,
+            ..Default::default()
+        })),
+        _ => None,
+    };
+
+    event.into_iter().collect()
+}
+
+fn pull_request_events(payload: PullRequestPayload) -> Vec<GitEvent> {
+    let pr = PullRequest {
+        id: payload.number,
+        sha: payload.pull_request.head.sha,
+        kind: ReviewKind::PullRequest,
+        patchset: None,
+        merge_sha: None,
+        mergeable: None,
+        diff: None,
+    };
+    let event = match payload.action.as_str() {
+        "opened" | "reopened" => Some(GitEvent::NewPullRequest(pr)),
+        "synchronize" | "synchronized" => Some(GitEvent::PullRequestUpdated(pr)),
+        "closed" => Some(GitEvent::PullRequestClosed(pr)),
+        _ => None,
+    };
+    event.into_iter().collect()
+}
+
+fn merge_request_events(payload: MergeRequestPayload) -> Vec<GitEvent> {
+    let attributes = payload.object_attributes;
+    let pr = PullRequest {
+        id: attributes.iid,
+        sha: attributes.last_commit.id,
+        kind: ReviewKind::MergeRequest,
+        patchset: None,
+        merge_sha: None,
+        mergeable: None,
+        diff: None,
+    };
+    let event = match attributes.action.as_deref() {
+        Some("open" | "reopen") => Some(GitEvent::NewPullRequest(pr)),
+        Some("update") if attributes.oldrev.is_some() => Some(GitEvent::PullRequestUpdated(pr)),
+        Some("close" | "merge") => Some(GitEvent::PullRequestClosed(pr)),
+        _ => None,
+    };
+    event.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const OLD_SHA: &str = "1111111111111111111111111111111111111111";
+    const NEW_SHA: &str = "2222222222222222222222222222222222222222";
+
+    fn push(ref_name: &str, before: &str, after: &str) -> Vec<GitEvent> {
+        let body = format!(
+            r#"{{"ref":"{}","before":"{}","after":"{}"}}"#,
+            ref_name, before, after
+        );
+        parse(WebhookSource::GitHub, "push", body.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn gitea_is_detected_before_github() {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-GitHub-Event", HeaderValue::from_static("push"));
+        headers.insert("X-Gitea-Event", HeaderValue::from_static("push"));
+        assert_eq!(
+            WebhookSource::detect(&headers),
+            Some((WebhookSource::Gitea, "push".to_string()))
+        );
+
+        headers.remove("X-Gitea-Event");
+        assert_eq!(
+            WebhookSource::detect(&headers),
+            Some((WebhookSource::GitHub, "push".to_string()))
+        );
+        assert_eq!(WebhookSource::detect(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn zero_sha_marks_creation_and_deletion() {
+        assert!(matches!(
+            push("refs/heads/main", ZERO_SHA, NEW_SHA).as_slice(),
+            [GitEvent::NewBranch(Branch { sha, .. })] if sha == NEW_SHA
+        ));
+        assert!(matches!(
+            push("refs/heads/main", OLD_SHA, ZERO_SHA).as_slice(),
+            [GitEvent::BranchDeleted(Branch { sha, .. })] if sha == OLD_SHA
+        ));
+        assert!(matches!(
+            push("refs/tags/v1.0.0", ZERO_SHA, NEW_SHA).as_slice(),
+            [GitEvent::NewTag(Tag { sha, .. })] if sha == NEW_SHA
+        ));
+        assert!(matches!(
+            push("refs/tags/v1.0.0", OLD_SHA, ZERO_SHA).as_slice(),
+            [GitEvent::TagDeleted(Tag { sha, .. })] if sha == OLD_SHA
+        ));
+    }
+
+    #[test]
+    fn branch_push_is_an_update_and_tag_moves_are_ignored() {
+        assert!(matches!(
+            push("refs/heads/main", OLD_SHA, NEW_SHA).as_slice(),
+            [GitEvent::BranchUpdated { old_sha, new_sha, .. }]
+                if old_sha == OLD_SHA && new_sha == NEW_SHA
+        ));
+        assert!(push("refs/tags/v1.0.0", OLD_SHA, NEW_SHA).is_empty());
+        assert!(push("refs/notes/commits", OLD_SHA, NEW_SHA).is_empty());
+    }
+
+    #[test]
+    fn pull_request_actions_map_to_events() {
+        let event = |action: &str| {
+            let body = format!(
+                r#"{{"action":"{}","number":7,"pull_request":{{"head":{{"sha":"{}"}}}}}}"#,
+                action, NEW_SHA
+            );
+            parse(WebhookSource::GitHub, "pull_request", body.as_bytes()).unwrap()
+        };
+        assert!(matches!(event("opened").as_slice(), [GitEvent::NewPullRequest(pr)] if pr.id == 7));
+        assert!(matches!(
+            event("synchronize").as_slice(),
+            [GitEvent::PullRequestUpdated(_)]
+        ));
+        assert!(matches!(
+            event("closed").as_slice(),
+            [GitEvent::PullRequestClosed(_)]
+        ));
+        assert!(event("labeled").is_empty());
+    }
+
+    #[test]
+    fn merge_request_update_needs_a_new_revision() {
+        let event = |action: &str, oldrev: Option<&str>| {
+            let oldrev = oldrev.map_or("null".to_string(), |rev| format!(r#""{}""#, rev));
+            let body = format!(
+                r#"{{"object_attributes":{{"iid":3,"action":"{}","oldrev":{},"last_commit":{{"id":"{}"}}}}}}"#,
+                action, oldrev, NEW_SHA
+            );
+            parse(WebhookSource::GitLab, "Merge Request Hook", body.as_bytes()).unwrap()
+        };
+        assert!(matches!(
+            event("update", Some(OLD_SHA)).as_slice(),
+            [GitEvent::PullRequestUpdated(pr)] if pr.kind == ReviewKind::MergeRequest
+        ));
+        assert!(event("update", None).is_empty());
+        assert!(matches!(
+            event("merge", None).as_slice(),
+            [GitEvent::PullRequestClosed(_)]
+        ));
+    }
+
+    #[test]
+    fn unknown_events_and_malformed_bodies() {
+        assert!(parse(WebhookSource::GitHub, "issues", b"not json")
+            .unwrap()
+            .is_empty());
+        assert!(parse(WebhookSource::GitHub, "push", b"not json").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use super::payloads::WebhookSource;
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn verify(source: WebhookSource, headers: &HeaderMap, secret: &str, body: &[u8]) -> bool {
+    match source {
+        WebhookSource::GitHub => header(headers, "X-Hub-Signature-256")
+            .and_then(|value| value.strip_prefix("sha256="))
+            .is_some_and(|signature| verify_hmac(secret, body, signature)),
+        WebhookSource::Gitea => header(headers, "X-Gitea-Signature")
+            .is_some_and(|signature| verify_hmac(secret, body, signature)),
+        WebhookSource::GitLab => header(headers, "X-Gitlab-Token")
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), secret.as_bytes())),
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn verify_hmac(secret: &str, body: &[u8], signature_hex: &str) -> bool {
+    let Ok(signature) = hex::decode(signature_hex) else {
+        return false;
+    };
+    let Ok(mut mac) = HmacSha256::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const SECRET: &str = "s3cret";
+    const BODY: &[u8] = br#"{"ref":"refs/heads/main"}"#;
+
+    fn signature(secret: &str, body: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn github_signature_is_checked_against_the_body() {
+        let valid = format!("sha256={}", signature(SECRET, BODY));
+        let headers = headers("X-Hub-Signature-256", &valid);
+        assert!(verify(WebhookSource::GitHub, &headers, SECRET, BODY));
+        assert!(!verify(WebhookSource::GitHub, &headers, "other", BODY));
+        assert!(!verify(WebhookSource::GitHub, &headers, SECRET, b"{}"));
+    }
+
+    #[test]
+    fn github_signature_needs_its_prefix() {
+        let headers = headers("X-Hub-Signature-256", &signature(SECRET, BODY));
+        assert!(!verify(WebhookSource::GitHub, &headers, SECRET, BODY));
+    }
+
+    #[test]
+    fn gitea_signature_is_bare_hex() {
+        let headers = headers("X-Gitea-Signature", &signature(SECRET, BODY));
+        assert!(verify(WebhookSource::Gitea, &headers, SECRET, BODY));
+        assert!(!verify(WebhookSource::Gitea, &headers, "other", BODY));
+
+        let malformed = self::headers("X-Gitea-Signature", "not hex");
+        assert!(!verify(WebhookSource::Gitea, &malformed, SECRET, BODY));
+    }
+
+    #[test]
+    fn gitlab_token_must_match_exactly() {
+        let accepts = |headers: HeaderMap| verify(WebhookSource::GitLab, &headers, SECRET, BODY);
+        assert!(accepts(headers("X-Gitlab-Token", SECRET)));
+        assert!(!accepts(headers("X-Gitlab-Token", "s3cre")));
+        assert!(!accepts(headers("X-Gitlab-Token", "s3creT")));
+        assert!(!accepts(HeaderMap::new()));
+    }
+}